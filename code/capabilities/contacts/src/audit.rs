@@ -0,0 +1,141 @@
+use crate::kinode::process::contacts::{Change, ContactEvent, Operation};
+use kinode_process_lib::{Address, NodeId};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+
+const DEFAULT_LIMIT: u32 = 1000;
+
+/// An operation that has been applied: what it changed, and how to revert it
+pub struct Applied {
+    pub operation: Operation,
+    pub old_value: Option<String>,
+    pub new_value: Option<String>,
+    /// operations that restore the state from before this one
+    pub inverse: Vec<Operation>,
+    pub events: Vec<ContactEvent>,
+}
+
+impl Applied {
+    /// An operation that had nothing to change, such as removing a missing contact
+    pub fn unchanged(operation: Operation) -> Self {
+        Applied {
+            operation,
+            old_value: None,
+            new_value: None,
+            inverse: vec![],
+            events: vec![],
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Entry {
+    change: Change,
+    inverse: Vec<Operation>,
+    undone: bool,
+}
+
+/// Append-only log of changes, keeping the latest `limit` of them
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AuditLog {
+    entries: VecDeque<Entry>,
+    next_id: u64,
+    limit: u32,
+}
+
+impl Default for AuditLog {
+    fn default() -> Self {
+        AuditLog {
+            entries: VecDeque::new(),
+            next_id: 0,
+            limit: DEFAULT_LIMIT,
+        }
+    }
+}
+
+impl AuditLog {
+    /// Log `applied`, unless it changed nothing.
+    pub fn record(&mut self, source: &Address, applied: &Applied, undoes: Option<u64>) {
+        if applied.old_value == applied.new_value {
+            return;
+        }
+        let timestamp = crate::now();
+        self.entries.push_back(Entry {
+            change: Change {
+                id: self.next_id,
+                source: source.to_string(),
+                timestamp,
+                operation: applied.operation.clone(),
+                old_value: applied.old_value.clone(),
+                new_value: applied.new_value.clone(),
+                undoes,
+            },
+            inverse: applied.inverse.clone(),
+            undone: false,
+        });
+        self.next_id += 1;
+        self.trim();
+    }
+
+    pub fn history(&self, node: &NodeId) -> Vec<Change> {
+        self.entries
+            .iter()
+            .filter(|entry| concerns(&entry.change.operation, node))
+            .map(|entry| entry.change.clone())
+            .collect()
+    }
+
+    /// Nodes added as contacts, most recently added first.
+    pub fn recently_added(&self) -> impl Iterator<Item = &NodeId> {
+        self.entries
+            .iter()
+            .rev()
+            .filter_map(|entry| match entry.change.operation {
+                Operation::AddContact(ref node) => Some(node),
+                _ => None,
+            })
+    }
+
+    /// Ids of the latest `count` changes that can still be undone, newest first,
+    /// each with the operations that revert it.
+    pub fn to_undo(&self, count: u32) -> Vec<(u64, Vec<Operation>)> {
+        self.entries
+            .iter()
+            .rev()
+            .filter(|entry| !entry.undone && entry.change.undoes.is_none())
+            .take(count as usize)
+            .map(|entry| (entry.change.id, entry.inverse.clone()))
+            .collect()
+    }
+
+    pub fn mark_undone(&mut self, ids: &[u64]) {
+        for entry in self.entries.iter_mut() {
+            if ids.contains(&entry.change.id) {
+                entry.undone = true;
+            }
+        }
+    }
+
+    pub fn set_limit(&mut self, limit: u32) {
+        self.limit = limit;
+        self.trim();
+    }
+
+    fn trim(&mut self) {
+        while self.entries.len() > self.limit as usize {
+            self.entries.pop_front();
+        }
+    }
+}
+
+fn concerns(operation: &Operation, node: &NodeId) -> bool {
+    match operation {
+        Operation::AddContact(n)
+        | Operation::RemoveContact(n)
+        | Operation::AddField((n, _, _))
+        | Operation::RemoveField((n, _))
+        | Operation::AddToGroup((_, n))
+        | Operation::RemoveFromGroup((_, n)) => n == node,
+        Operation::CreateGroup(_) | Operation::RenameGroup(_) | Operation::DeleteGroup(_) => false,
+    }
+}
@@ -0,0 +1,364 @@
+//! Conversion of contacts to and from vCard 4.0 and CSV.
+//!
+//! In vCard, each node is a card with an `X-KINODE-NODE` property.
+//! Text fields with a standard vCard equivalent (e.g. `email` as `EMAIL`) use it,
+//! and every other field is an `X-KINODE-FIELD` property holding its JSON value.
+//! In CSV, the first column is the node and each other column a field:
+//! cells hold text as-is and other values as JSON.
+use crate::{Contact, Contacts};
+use kinode_process_lib::NodeId;
+use std::collections::{BTreeSet, HashMap};
+
+/// Contact fields and the standard vCard properties they map to
+const VCARD_PROPERTIES: [(&str, &str); 6] = [
+    ("name", "FN"),
+    ("email", "EMAIL"),
+    ("phone", "TEL"),
+    ("url", "URL"),
+    ("nickname", "NICKNAME"),
+    ("note", "NOTE"),
+];
+const NODE_PROPERTY: &str = "X-KINODE-NODE";
+const FIELD_PROPERTY: &str = "X-KINODE-FIELD";
+/// Maximum length of a vCard line in octets, excluding the line break
+const VCARD_LINE_LENGTH: usize = 75;
+
+fn sorted_contacts(contacts: &Contacts) -> Vec<(&NodeId, &Contact)> {
+    let mut contacts: Vec<(&NodeId, &Contact)> = contacts.0.iter().collect();
+    contacts.sort_by(|(a, _), (b, _)| a.cmp(b));
+    contacts
+}
+
+pub fn to_vcard(contacts: &Contacts) -> String {
+    let mut vcard = String::new();
+    for (node, contact) in sorted_contacts(contacts) {
+        let mut lines = vec![
+            "BEGIN:VCARD".to_string(),
+            "VERSION:4.0".to_string(),
+            format!("{NODE_PROPERTY}:{}", escape_vcard(node)),
+        ];
+        let has_name = contact
+            .0
+            .get("name")
+            .and_then(text_values)
+            .is_some_and(|names| !names.is_empty());
+        if !has_name {
+            // FN is required, so fall back to the node
+            lines.push(format!("FN:{}", escape_vcard(node)));
+        }
+        let mut fields: Vec<(&String, &serde_json::Value)> = contact.0.iter().collect();
+        fields.sort_by(|(a, _), (b, _)| a.cmp(b));
+        for (field, value) in fields {
+            let property = VCARD_PROPERTIES
+                .iter()
+                .find(|(name, _)| name == field)
+                .map(|(_, property)| *property);
+            match (property, text_values(value)) {
+                (Some(property), Some(texts)) if !texts.is_empty() => {
+                    for text in texts {
+                        lines.push(format!("{property}:{}", escape_vcard(text)));
+                    }
+                }
+                _ => lines.push(format!(
+                    "{FIELD_PROPERTY};NAME={}:{}",
+                    quote_parameter(field),
+                    escape_vcard(&value.to_string()),
+                )),
+            }
+        }
+        lines.push("END:VCARD".to_string());
+        for line in lines {
+            vcard.push_str(&fold_vcard_line(&line));
+        }
+    }
+    vcard
+}
+
+/// Parse cards into contacts: every card must have a node.
+pub fn from_vcard(vcard: &str) -> Result<Vec<(NodeId, Contact)>, String> {
+    let mut contacts = vec![];
+    let mut card: Option<(Option<NodeId>, HashMap<String, Vec<serde_json::Value>>)> = None;
+    for line in unfold_vcard_lines(vcard) {
+        let Some((name_and_parameters, value)) = split_vcard_line(&line) else {
+            return Err(format!("Malformed vCard line: {line}"));
+        };
+        let mut parts = name_and_parameters.split(';');
+        // property names may be prefixed with a group, e.g. `item1.EMAIL`
+        let name = parts
+            .next()
+            .unwrap_or_default()
+            .rsplit('.')
+            .next()
+            .unwrap_or_default()
+            .to_ascii_uppercase();
+        let parameters: Vec<&str> = parts.collect();
+        match (name.as_str(), card.as_mut()) {
+            ("BEGIN", None) if value.eq_ignore_ascii_case("VCARD") => {
+                card = Some((None, HashMap::new()));
+            }
+            ("END", Some(_)) if value.eq_ignore_ascii_case("VCARD") => {
+                let (node, fields) = card.take().unwrap();
+                let Some(node) = node else {
+                    return Err(format!("vCard without {NODE_PROPERTY}"));
+                };
+                let mut contact = Contact(HashMap::new());
+                for (field, mut values) in fields {
+                    let value = if values.len() == 1 {
+                        values.pop().unwrap()
+                    } else {
+                        serde_json::Value::Array(values)
+                    };
+                    contact.0.insert(field, value);
+                }
+                // FN only stands in for the node when there is no `name` field
+                if contact.0.get("name").and_then(|name| name.as_str()) == Some(node.as_str()) {
+                    contact.0.remove("name");
+                }
+                contacts.push((node, contact));
+            }
+            (_, None) => return Err(format!("vCard property outside of a card: {line}")),
+            ("BEGIN" | "END", Some(_)) => return Err(format!("Unexpected vCard line: {line}")),
+            ("VERSION", Some(_)) => {}
+            (NODE_PROPERTY, Some((node, _))) => *node = Some(unescape_vcard(value)),
+            (FIELD_PROPERTY, Some((_, fields))) => {
+                let Some(field) = parameters.iter().find_map(|parameter| {
+                    let (key, value) = parameter.split_once('=')?;
+                    key.eq_ignore_ascii_case("NAME")
+                        .then(|| value.trim_matches('"').to_string())
+                }) else {
+                    return Err(format!("{FIELD_PROPERTY} without NAME: {line}"));
+                };
+                let Ok(value) = serde_json::from_str(&unescape_vcard(value)) else {
+                    return Err(format!("Malformed {FIELD_PROPERTY} value: {line}"));
+                };
+                fields.entry(field).or_default().push(value);
+            }
+            (property, Some((_, fields))) => {
+                // standard properties without a field equivalent are dropped
+                if let Some((field, _)) = VCARD_PROPERTIES.iter().find(|(_, p)| *p == property) {
+                    fields
+                        .entry(field.to_string())
+                        .or_default()
+                        .push(serde_json::Value::String(unescape_vcard(value)));
+                }
+            }
+        }
+    }
+    if card.is_some() {
+        return Err("vCard missing END:VCARD".to_string());
+    }
+    Ok(contacts)
+}
+
+/// Text of a string, or of a list of strings: the values with a standard vCard equivalent
+fn text_values(value: &serde_json::Value) -> Option<Vec<&str>> {
+    match value {
+        serde_json::Value::String(text) => Some(vec![text]),
+        serde_json::Value::Array(values) => values.iter().map(|value| value.as_str()).collect(),
+        _ => None,
+    }
+}
+
+fn escape_vcard(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            ',' => escaped.push_str("\\,"),
+            ';' => escaped.push_str("\\;"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => {}
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn unescape_vcard(text: &str) -> String {
+    let mut unescaped = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n' | 'N') => unescaped.push('\n'),
+            Some(c) => unescaped.push(c),
+            None => unescaped.push('\\'),
+        }
+    }
+    unescaped
+}
+
+/// Parameter values may not contain `"`, and must be quoted if they contain `:`, `;` or `,`.
+fn quote_parameter(value: &str) -> String {
+    format!("\"{}\"", value.replace('"', "'"))
+}
+
+/// Split a line into its name and parameters, and its value, at the first `:` not in quotes.
+fn split_vcard_line(line: &str) -> Option<(&str, &str)> {
+    let mut in_quotes = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            '"' => in_quotes = !in_quotes,
+            ':' if !in_quotes => return Some((&line[..i], &line[i + 1..])),
+            _ => {}
+        }
+    }
+    None
+}
+
+fn fold_vcard_line(line: &str) -> String {
+    let mut folded = String::with_capacity(line.len() + 2);
+    let mut line_length = 0;
+    for c in line.chars() {
+        if line_length + c.len_utf8() > VCARD_LINE_LENGTH {
+            folded.push_str("\r\n ");
+            // the leading space counts towards the continuation line
+            line_length = 1;
+        }
+        folded.push(c);
+        line_length += c.len_utf8();
+    }
+    folded.push_str("\r\n");
+    folded
+}
+
+fn unfold_vcard_lines(vcard: &str) -> Vec<String> {
+    let mut lines: Vec<String> = vec![];
+    for line in vcard.lines() {
+        match (line.strip_prefix([' ', '\t']), lines.last_mut()) {
+            (Some(continuation), Some(last)) => last.push_str(continuation),
+            _ if line.trim().is_empty() => {}
+            _ => lines.push(line.to_string()),
+        }
+    }
+    lines
+}
+
+pub fn to_csv(contacts: &Contacts) -> String {
+    let fields: BTreeSet<&String> = contacts
+        .0
+        .values()
+        .flat_map(|contact| contact.0.keys())
+        .collect();
+    let mut csv = String::new();
+    let header: Vec<&str> = std::iter::once("node")
+        .chain(fields.iter().map(|field| field.as_str()))
+        .collect();
+    push_csv_row(&mut csv, header);
+    for (node, contact) in sorted_contacts(contacts) {
+        let cells: Vec<String> = fields
+            .iter()
+            .map(|field| contact.0.get(*field).map(to_csv_cell).unwrap_or_default())
+            .collect();
+        push_csv_row(
+            &mut csv,
+            std::iter::once(node.as_str())
+                .chain(cells.iter().map(|cell| cell.as_str()))
+                .collect(),
+        );
+    }
+    csv
+}
+
+/// Parse rows into contacts: the header names each column, and empty cells are absent fields.
+pub fn from_csv(csv: &str) -> Result<Vec<(NodeId, Contact)>, String> {
+    let mut rows = parse_csv(csv)?.into_iter();
+    let Some(header) = rows.next() else {
+        return Ok(vec![]);
+    };
+    if header.first().map(|column| column.as_str()) != Some("node") {
+        return Err("CSV header must start with a node column".to_string());
+    }
+    let mut contacts = vec![];
+    for row in rows {
+        if row.len() != header.len() {
+            return Err(format!(
+                "CSV row has {} cells, expected {}",
+                row.len(),
+                header.len()
+            ));
+        }
+        let mut cells = row.into_iter();
+        let node = cells.next().unwrap();
+        let contact = Contact(
+            header[1..]
+                .iter()
+                .zip(cells)
+                .filter(|(_, cell)| !cell.is_empty())
+                .map(|(field, cell)| (field.clone(), from_csv_cell(cell)))
+                .collect(),
+        );
+        contacts.push((node, contact));
+    }
+    Ok(contacts)
+}
+
+/// Write text as-is unless it would read back as some other JSON value.
+fn to_csv_cell(value: &serde_json::Value) -> String {
+    match value.as_str() {
+        Some(text)
+            if !text.is_empty() && serde_json::from_str::<serde_json::Value>(text).is_err() =>
+        {
+            text.to_string()
+        }
+        _ => value.to_string(),
+    }
+}
+
+fn from_csv_cell(cell: String) -> serde_json::Value {
+    serde_json::from_str(&cell).unwrap_or(serde_json::Value::String(cell))
+}
+
+fn push_csv_row(csv: &mut String, cells: Vec<&str>) {
+    let cells: Vec<String> = cells
+        .into_iter()
+        .map(|cell| {
+            if cell.contains([',', '"', '\n', '\r']) {
+                format!("\"{}\"", cell.replace('"', "\"\""))
+            } else {
+                cell.to_string()
+            }
+        })
+        .collect();
+    csv.push_str(&cells.join(","));
+    csv.push_str("\r\n");
+}
+
+/// Parse RFC 4180 CSV, where quoted cells may contain commas, quotes and line breaks.
+fn parse_csv(csv: &str) -> Result<Vec<Vec<String>>, String> {
+    let mut rows = vec![];
+    let mut row = vec![];
+    let mut cell = String::new();
+    let mut in_quotes = false;
+    let mut chars = csv.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (c, in_quotes) {
+            ('"', true) if chars.peek() == Some(&'"') => {
+                chars.next();
+                cell.push('"');
+            }
+            ('"', true) => in_quotes = false,
+            ('"', false) if cell.is_empty() => in_quotes = true,
+            (',', false) => row.push(std::mem::take(&mut cell)),
+            ('\r', false) => {}
+            ('\n', false) => {
+                row.push(std::mem::take(&mut cell));
+                rows.push(std::mem::take(&mut row));
+            }
+            (c, _) => cell.push(c),
+        }
+    }
+    if in_quotes {
+        return Err("CSV has an unterminated quoted cell".to_string());
+    }
+    if !cell.is_empty() || !row.is_empty() {
+        row.push(cell);
+        rows.push(row);
+    }
+    // skip blank lines
+    rows.retain(|row| row.len() > 1 || row.first().is_some_and(|cell| !cell.is_empty()));
+    Ok(rows)
+}
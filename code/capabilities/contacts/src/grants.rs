@@ -0,0 +1,77 @@
+use crate::kinode::process::contacts::{Capability, Grant, IssuedCapability};
+use kinode_process_lib::Address;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Capabilities we issued at runtime, and those since revoked
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Grants {
+    outstanding: BTreeMap<u64, Grant>,
+    revoked: BTreeSet<u64>,
+    next_id: u64,
+}
+
+impl Grants {
+    /// Record a new grant of `capability` to `grantee`.
+    pub fn issue(
+        &mut self,
+        grantee: &Address,
+        capability: Capability,
+        expires_in: Option<u64>,
+    ) -> Grant {
+        let grant = Grant {
+            id: self.next_id,
+            grantee: grantee.to_string(),
+            capability,
+            expires: expires_in.map(|seconds| crate::now() + seconds),
+        };
+        self.next_id += 1;
+        self.outstanding.insert(grant.id, grant.clone());
+        grant
+    }
+
+    /// Params of the capability to issue for `grant`.
+    pub fn params(grant: &Grant) -> String {
+        serde_json::to_string(&IssuedCapability {
+            capability: grant.capability.clone(),
+            grant: grant.id,
+            expires: grant.expires,
+        })
+        .unwrap()
+    }
+
+    /// Grants that have neither expired nor been revoked.
+    pub fn outstanding(&mut self) -> Vec<Grant> {
+        let now = crate::now();
+        self.outstanding
+            .retain(|_, grant| !grant.expires.is_some_and(|expires| expires <= now));
+        self.outstanding.values().cloned().collect()
+    }
+
+    pub fn revoke(&mut self, id: u64) -> Result<(), String> {
+        if self.outstanding.remove(&id).is_none() {
+            return Err(format!("No outstanding grant {id}"));
+        }
+        self.revoked.insert(id);
+        Ok(())
+    }
+
+    /// The capability granted by `params` of a capability we issued,
+    /// unless it has expired or been revoked. Capabilities granted through
+    /// the manifest carry the bare capability and never expire.
+    pub fn check(&self, params: &str) -> Option<Capability> {
+        if let Ok(capability) = serde_json::from_str::<Capability>(params) {
+            return Some(capability);
+        }
+        let issued: IssuedCapability = serde_json::from_str(params).ok()?;
+        if self.revoked.contains(&issued.grant) {
+            return None;
+        }
+        if let Some(expires) = issued.expires {
+            if expires <= crate::now() {
+                return None;
+            }
+        }
+        Some(issued.capability)
+    }
+}
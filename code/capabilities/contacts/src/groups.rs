@@ -0,0 +1,81 @@
+use kinode_process_lib::NodeId;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Named groups of contacts, e.g. to message everyone in a group at once
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Groups(BTreeMap<String, BTreeSet<NodeId>>);
+
+impl Groups {
+    pub fn names(&self) -> Vec<String> {
+        self.0.keys().cloned().collect()
+    }
+
+    pub fn members(&self, group: &str) -> Result<&BTreeSet<NodeId>, String> {
+        self.0.get(group).ok_or_else(|| format!("No group {group}"))
+    }
+
+    pub fn create(&mut self, group: String) -> Result<(), String> {
+        if group.is_empty() {
+            return Err("Group name must not be empty".to_string());
+        }
+        if self.0.contains_key(&group) {
+            return Err(format!("Group {group} already exists"));
+        }
+        self.0.insert(group, BTreeSet::new());
+        Ok(())
+    }
+
+    pub fn rename(&mut self, group: &str, new_name: String) -> Result<(), String> {
+        if new_name.is_empty() {
+            return Err("Group name must not be empty".to_string());
+        }
+        if self.0.contains_key(&new_name) {
+            return Err(format!("Group {new_name} already exists"));
+        }
+        let Some(members) = self.0.remove(group) else {
+            return Err(format!("No group {group}"));
+        };
+        self.0.insert(new_name, members);
+        Ok(())
+    }
+
+    pub fn delete(&mut self, group: &str) -> Result<(), String> {
+        self.0
+            .remove(group)
+            .map(|_| ())
+            .ok_or_else(|| format!("No group {group}"))
+    }
+
+    pub fn add_member(&mut self, group: &str, node: NodeId) -> Result<(), String> {
+        let Some(members) = self.0.get_mut(group) else {
+            return Err(format!("No group {group}"));
+        };
+        members.insert(node);
+        Ok(())
+    }
+
+    pub fn remove_member(&mut self, group: &str, node: &NodeId) -> Result<(), String> {
+        let Some(members) = self.0.get_mut(group) else {
+            return Err(format!("No group {group}"));
+        };
+        members.remove(node);
+        Ok(())
+    }
+
+    /// Groups `node` is a member of.
+    pub fn groups_of(&self, node: &NodeId) -> Vec<String> {
+        self.0
+            .iter()
+            .filter(|(_, members)| members.contains(node))
+            .map(|(group, _)| group.clone())
+            .collect()
+    }
+
+    /// Remove a node that is no longer a contact from every group.
+    pub fn remove_from_all(&mut self, node: &NodeId) {
+        for members in self.0.values_mut() {
+            members.remove(node);
+        }
+    }
+}
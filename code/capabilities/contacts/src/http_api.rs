@@ -0,0 +1,97 @@
+use crate::kinode::process::contacts;
+use crate::{handle_contacts_request, ContactsState};
+use kinode_process_lib::{
+    get_blob, homepage,
+    http::{
+        server::{send_response, HttpBindingConfig, HttpServer, HttpServerRequest},
+        StatusCode,
+    },
+    Address,
+};
+use std::collections::HashMap;
+
+/// Number of contacts the homepage widget shows
+const WIDGET_CONTACTS: u32 = 5;
+
+const WIDGET: &str = include_str!("./widget.html");
+
+const CONTACTS_PATH: &str = "/api/contacts";
+const CONTACT_PATH: &str = "/api/contacts/:node";
+const FIELD_PATH: &str = "/api/contacts/:node/:field";
+const RECENT_PATH: &str = "/api/recent";
+
+/// Bind the REST API and UI, and add our widget to the homepage.
+/// Every path requires the node owner to be logged in.
+pub fn serve(our: &Address) -> HttpServer {
+    let mut server = HttpServer::new(5);
+    for path in [CONTACTS_PATH, CONTACT_PATH, FIELD_PATH, RECENT_PATH] {
+        server
+            .bind_http_path(path, HttpBindingConfig::default())
+            .unwrap();
+    }
+    server
+        .serve_file(
+            our,
+            "ui/index.html",
+            vec!["/"],
+            HttpBindingConfig::default(),
+        )
+        .unwrap();
+    homepage::add_to_homepage("Contacts", None, Some("/"), Some(WIDGET));
+    server
+}
+
+/// Translate a REST request into a contacts request and answer it with
+/// `handle_contacts_request()`. The node owner holds every capability,
+/// so none are checked.
+pub fn handle_http_request(state: &mut ContactsState, source: &Address, body: &[u8]) {
+    let Ok(HttpServerRequest::Http(http_request)) = HttpServerRequest::from_bytes(body) else {
+        // we do not bind WebSockets
+        return;
+    };
+    let Ok(method) = http_request.method() else {
+        return send_error(StatusCode::BAD_REQUEST, "Malformed request");
+    };
+    let path = http_request.bound_path(Some(&state.our.process.to_string()));
+    let params = http_request.url_params();
+    let node = params.get("node").cloned().unwrap_or_default();
+    let field = params.get("field").cloned().unwrap_or_default();
+    let request = match (path, method.as_str()) {
+        (CONTACTS_PATH, "GET") => contacts::Request::GetAllContacts,
+        (CONTACT_PATH, "GET") => contacts::Request::GetContact(node),
+        (CONTACT_PATH, "POST") => contacts::Request::AddContact(node),
+        (CONTACT_PATH, "DELETE") => contacts::Request::RemoveContact(node),
+        // the body is the JSON value of the field
+        (FIELD_PATH, "PUT") => {
+            let Some(value) = get_blob().and_then(|blob| String::from_utf8(blob.bytes).ok()) else {
+                return send_error(StatusCode::BAD_REQUEST, "Missing value");
+            };
+            contacts::Request::AddField((node, field, value))
+        }
+        (FIELD_PATH, "DELETE") => contacts::Request::RemoveField((node, field)),
+        (RECENT_PATH, "GET") => contacts::Request::GetRecent(WIDGET_CONTACTS),
+        _ => return send_error(StatusCode::METHOD_NOT_ALLOWED, "Method not allowed"),
+    };
+    let (response, blob) =
+        handle_contacts_request(state, source, &serde_json::to_vec(&request).unwrap(), None);
+    match (response, blob) {
+        (contacts::Response::Err(e), _) => send_error(StatusCode::BAD_REQUEST, &e),
+        (_, Some(blob)) => send_json(StatusCode::OK, blob.bytes),
+        (response, None) => send_json(StatusCode::OK, serde_json::to_vec(&response).unwrap()),
+    }
+}
+
+fn send_error(status: StatusCode, error: &str) {
+    send_json(status, serde_json::to_vec(error).unwrap());
+}
+
+fn send_json(status: StatusCode, body: Vec<u8>) {
+    send_response(
+        status,
+        Some(HashMap::from([(
+            "Content-Type".to_string(),
+            "application/json".to_string(),
+        )])),
+        body,
+    );
+}
@@ -1,33 +1,140 @@
 use crate::kinode::process::contacts;
 use kinode_process_lib::{
-    await_message, call_init, get_typed_state, kiprintln, set_state, Address, Capability,
-    LazyLoadBlob, Message, NodeId, Response,
+    await_message, call_init, get_blob, get_state, kiprintln, set_state,
+    vfs::{create_drive, open_file},
+    Address, Capability, LazyLoadBlob, Message, NodeId, Request, Response,
 };
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap, HashSet};
 
-#[derive(Debug, Serialize, Deserialize)]
+mod audit;
+use audit::{Applied, AuditLog};
+mod formats;
+mod grants;
+use grants::Grants;
+mod groups;
+use groups::Groups;
+mod http_api;
+mod migrations;
+use migrations::Versioned;
+mod schema;
+use schema::Schema;
+mod search;
+mod subscriptions;
+use subscriptions::Subscriptions;
+
+/// Seconds a peer has to respond with its card
+const CARD_TIMEOUT: u64 = 5;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 struct Contact(HashMap<String, serde_json::Value>);
 
-#[derive(Debug, Serialize, Deserialize)]
+impl Contact {
+    fn visible(&self, fields: &ReadableFields) -> Contact {
+        match fields {
+            ReadableFields::All => Contact(self.0.clone()),
+            ReadableFields::Only(fields) => Contact(
+                self.0
+                    .iter()
+                    .filter(|(field, _)| fields.contains(*field))
+                    .map(|(field, value)| (field.clone(), value.clone()))
+                    .collect(),
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 struct Contacts(HashMap<NodeId, Contact>);
 
+/// Fields of each contact a request may read
+#[derive(Debug, Clone, Serialize, Deserialize)]
+enum ReadableFields {
+    All,
+    Only(HashSet<String>),
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 struct ContactsState {
     our: Address,
     contacts: Contacts,
+    #[serde(default)]
+    schema: Schema,
+    #[serde(default)]
+    subscriptions: Subscriptions,
+    #[serde(default)]
+    groups: Groups,
+    #[serde(default)]
+    audit_log: AuditLog,
+    #[serde(default)]
+    grants: Grants,
+    /// fields of our own contact that peers may fetch as our card
+    #[serde(default)]
+    card_fields: BTreeSet<String>,
 }
 
 impl ContactsState {
+    /// Load our saved state, upgrading it from older layouts. A state that cannot
+    /// be loaded is backed up to the backups drive rather than thrown away.
     fn new(our: Address) -> Self {
-        get_typed_state(|bytes| serde_json::from_slice(bytes)).unwrap_or(Self {
+        let Some(bytes) = get_state() else {
+            return Self::empty(our);
+        };
+        let loaded = migrations::upgrade(&bytes).and_then(|state| {
+            serde_json::from_value::<Self>(state).map_err(|e| format!("Malformed state: {e}"))
+        });
+        match loaded {
+            Ok(state) => {
+                // save in the current layout
+                state.save();
+                state
+            }
+            Err(e) => {
+                let backup = Self::back_up(&our, &bytes)
+                    .unwrap_or_else(|backup_e| panic!("{e}, and failed to back it up: {backup_e}"));
+                kiprintln!("{e}: backed up to {backup} and starting afresh");
+                Self::empty(our)
+            }
+        }
+    }
+
+    fn empty(our: Address) -> Self {
+        Self {
             our,
             contacts: Contacts(HashMap::new()),
-        })
+            schema: Schema::default(),
+            subscriptions: Subscriptions::default(),
+            groups: Groups::default(),
+            audit_log: AuditLog::default(),
+            grants: Grants::default(),
+            card_fields: BTreeSet::new(),
+        }
+    }
+
+    /// The path of file `name` in our exports drive. Only bare file names are
+    /// accepted, so requests cannot reach outside the drive.
+    fn exports_path(&self, name: &str) -> Result<String, String> {
+        if name.is_empty() || name.contains('/') || name.contains("..") {
+            return Err(format!("Invalid file name {name:?}"));
+        }
+        Ok(format!("{}/exports/{name}", self.our.package_id()))
+    }
+
+    /// Write `bytes` to a new file in the backups drive, returning its path.
+    fn back_up(our: &Address, bytes: &[u8]) -> Result<String, String> {
+        let drive = create_drive(our.package_id(), "backups", None).map_err(|e| e.to_string())?;
+        let path = format!("{drive}/state-{}.json", now());
+        open_file(&path, true, None)
+            .and_then(|file| file.write(bytes))
+            .map_err(|e| e.to_string())?;
+        Ok(path)
     }
 
     fn save(&self) {
-        set_state(&serde_json::to_vec(&self).expect("Failed to serialize contacts state!"));
+        set_state(
+            &serde_json::to_vec(&Versioned::new(self))
+                .expect("Failed to serialize contacts state!"),
+        );
     }
 
     fn contacts(&self) -> &Contacts {
@@ -38,32 +145,409 @@ impl ContactsState {
         self.contacts.0.get(&node)
     }
 
-    fn add_contact(&mut self, node: NodeId) {
-        self.contacts.0.insert(node, Contact(HashMap::new()));
-        self.save();
+    fn visible_contacts(&self, fields: &ReadableFields) -> Contacts {
+        Contacts(
+            self.contacts
+                .0
+                .iter()
+                .map(|(node, contact)| (node.clone(), contact.visible(fields)))
+                .collect(),
+        )
     }
 
-    fn remove_contact(&mut self, node: NodeId) {
-        self.contacts.0.remove(&node);
-        self.save();
+    /// Apply `operation` to our contacts and groups without saving them,
+    /// returning what changed so it can be logged and published once saved.
+    fn apply(&mut self, operation: contacts::Operation) -> Result<Applied, String> {
+        let mut events = vec![];
+        let mut inverse = vec![];
+        let (old_value, new_value) = match operation {
+            contacts::Operation::AddContact(ref node) => {
+                let old = self
+                    .contacts
+                    .0
+                    .insert(node.clone(), Contact(HashMap::new()));
+                events.push(contacts::ContactEvent::ContactAdded(node.clone()));
+                match old {
+                    None => inverse.push(contacts::Operation::RemoveContact(node.clone())),
+                    Some(ref old) => inverse.extend(restore_fields(node, old)),
+                }
+                (old.map(|old| to_json(&old)), Some("{}".to_string()))
+            }
+            contacts::Operation::AddField((ref node, ref field, ref value)) => {
+                let Ok(value) = serde_json::from_str::<serde_json::Value>(value) else {
+                    return Err("Malformed value".to_string());
+                };
+                self.schema.validate(field, &value)?;
+                let old = match self.contacts.0.get(node) {
+                    None => {
+                        inverse.push(contacts::Operation::RemoveContact(node.clone()));
+                        None
+                    }
+                    Some(contact) => {
+                        let old = contact.0.get(field).map(|old| old.to_string());
+                        inverse.push(match old {
+                            None => contacts::Operation::RemoveField((node.clone(), field.clone())),
+                            Some(ref old) => contacts::Operation::AddField((
+                                node.clone(),
+                                field.clone(),
+                                old.clone(),
+                            )),
+                        });
+                        old
+                    }
+                };
+                let new = Some(value.to_string());
+                events.extend(self.set_field(node.clone(), field.clone(), value));
+                (old, new)
+            }
+            contacts::Operation::RemoveContact(ref node) => {
+                let groups = self.groups.groups_of(node);
+                let Some(old) = self.contacts.0.remove(node) else {
+                    return Ok(Applied::unchanged(operation.clone()));
+                };
+                self.groups.remove_from_all(node);
+                events.push(contacts::ContactEvent::ContactRemoved(node.clone()));
+                inverse.push(contacts::Operation::AddContact(node.clone()));
+                inverse.extend(restore_fields(node, &old));
+                inverse.extend(
+                    groups
+                        .into_iter()
+                        .map(|group| contacts::Operation::AddToGroup((group, node.clone()))),
+                );
+                (Some(to_json(&old)), None)
+            }
+            contacts::Operation::RemoveField((ref node, ref field)) => {
+                let Some(old) = self
+                    .contacts
+                    .0
+                    .get_mut(node)
+                    .and_then(|contact| contact.0.remove(field))
+                else {
+                    return Ok(Applied::unchanged(operation.clone()));
+                };
+                events.push(contacts::ContactEvent::FieldRemoved((
+                    node.clone(),
+                    field.clone(),
+                )));
+                inverse.push(contacts::Operation::AddField((
+                    node.clone(),
+                    field.clone(),
+                    old.to_string(),
+                )));
+                (Some(old.to_string()), None)
+            }
+            contacts::Operation::CreateGroup(ref group) => {
+                self.groups.create(group.clone())?;
+                inverse.push(contacts::Operation::DeleteGroup(group.clone()));
+                (None, Some("[]".to_string()))
+            }
+            contacts::Operation::RenameGroup((ref group, ref new_name)) => {
+                self.groups.rename(group, new_name.clone())?;
+                inverse.push(contacts::Operation::RenameGroup((
+                    new_name.clone(),
+                    group.clone(),
+                )));
+                (Some(to_json(group)), Some(to_json(new_name)))
+            }
+            contacts::Operation::DeleteGroup(ref group) => {
+                let members = self.groups.members(group)?.clone();
+                self.groups.delete(group)?;
+                inverse.push(contacts::Operation::CreateGroup(group.clone()));
+                inverse.extend(members.iter().map(|member| {
+                    contacts::Operation::AddToGroup((group.clone(), member.clone()))
+                }));
+                (Some(to_json(&members)), None)
+            }
+            contacts::Operation::AddToGroup((ref group, ref node)) => {
+                if !self.contacts.0.contains_key(node) {
+                    return Err(format!("{node} is not a contact"));
+                }
+                let old = to_json(self.groups.members(group)?);
+                self.groups.add_member(group, node.clone())?;
+                inverse.push(contacts::Operation::RemoveFromGroup((
+                    group.clone(),
+                    node.clone(),
+                )));
+                (Some(old), Some(to_json(self.groups.members(group)?)))
+            }
+            contacts::Operation::RemoveFromGroup((ref group, ref node)) => {
+                let old = to_json(self.groups.members(group)?);
+                self.groups.remove_member(group, node)?;
+                inverse.push(contacts::Operation::AddToGroup((
+                    group.clone(),
+                    node.clone(),
+                )));
+                (Some(old), Some(to_json(self.groups.members(group)?)))
+            }
+        };
+        Ok(Applied {
+            operation,
+            old_value,
+            new_value,
+            inverse,
+            events,
+        })
     }
 
-    fn add_field(&mut self, node: NodeId, field: String, value: serde_json::Value) {
+    /// Set `field` of `node`, adding `node` as a contact if it is not one.
+    fn set_field(
+        &mut self,
+        node: NodeId,
+        field: String,
+        value: serde_json::Value,
+    ) -> Vec<contacts::ContactEvent> {
+        let mut events = vec![];
+        if !self.contacts.0.contains_key(&node) {
+            events.push(contacts::ContactEvent::ContactAdded(node.clone()));
+        }
+        events.push(contacts::ContactEvent::FieldSet((
+            node.clone(),
+            field.clone(),
+            value.to_string(),
+        )));
         self.contacts
             .0
             .entry(node)
             .or_insert_with(|| Contact(HashMap::new()))
             .0
             .insert(field, value);
+        events
+    }
+
+    /// Apply every operation, or none of them if any fails,
+    /// returning the index of the failed operation.
+    fn apply_all(
+        &mut self,
+        operations: Vec<contacts::Operation>,
+    ) -> Result<Vec<Applied>, (usize, String)> {
+        let snapshot = (self.contacts.clone(), self.groups.clone());
+        let mut applied = vec![];
+        for (i, operation) in operations.into_iter().enumerate() {
+            match self.apply(operation) {
+                Ok(operation_applied) => applied.push(operation_applied),
+                Err(e) => {
+                    (self.contacts, self.groups) = snapshot;
+                    return Err((i, e));
+                }
+            }
+        }
+        Ok(applied)
+    }
+
+    /// Log and save changes made by `apply()`, and tell subscribers about them.
+    fn commit(&mut self, source: &Address, applied: Vec<(Applied, Option<u64>)>) {
+        for (applied, undoes) in applied.iter() {
+            self.audit_log.record(source, applied, *undoes);
+        }
+        self.save();
+        let mut dropped = false;
+        for (applied, _) in applied {
+            for event in applied.events.iter() {
+                dropped |= self.subscriptions.publish(&self.grants, event);
+            }
+        }
+        if dropped {
+            self.save();
+        }
+    }
+
+    fn apply_and_commit(
+        &mut self,
+        source: &Address,
+        operation: contacts::Operation,
+    ) -> Result<(), String> {
+        let applied = self.apply(operation)?;
+        self.commit(source, vec![(applied, None)]);
+        Ok(())
+    }
+
+    /// Apply every operation with a single save, or none of them if any fails.
+    fn apply_batch(
+        &mut self,
+        source: &Address,
+        operations: Vec<contacts::Operation>,
+    ) -> Result<(), String> {
+        let applied = self
+            .apply_all(operations)
+            .map_err(|(i, e)| format!("Operation {i} failed: {e}"))?;
+        self.commit(source, applied.into_iter().map(|a| (a, None)).collect());
+        Ok(())
+    }
+
+    /// Revert the latest `count` changes, or none of them if any cannot be reverted.
+    fn undo(&mut self, source: &Address, count: u32) -> Result<u32, String> {
+        let to_undo = self.audit_log.to_undo(count);
+        let mut operations = vec![];
+        let mut undoes = vec![];
+        for (id, inverse) in to_undo.iter() {
+            for operation in inverse {
+                operations.push(operation.clone());
+                undoes.push(*id);
+            }
+        }
+        let applied = self
+            .apply_all(operations)
+            .map_err(|(i, e)| format!("Failed to undo change {}: {e}", undoes[i]))?;
+        let ids: Vec<u64> = to_undo.iter().map(|(id, _)| *id).collect();
+        self.audit_log.mark_undone(&ids);
+        self.commit(
+            source,
+            applied
+                .into_iter()
+                .zip(undoes)
+                .map(|(applied, id)| (applied, Some(id)))
+                .collect(),
+        );
+        Ok(ids.len() as u32)
+    }
+
+    /// Merge `imported` into our contacts with a single save,
+    /// keeping existing values unless `overwrite` is set.
+    fn import_contacts(
+        &mut self,
+        source: &Address,
+        imported: Vec<(NodeId, Contact)>,
+        overwrite: bool,
+    ) -> contacts::ImportReport {
+        let mut report = contacts::ImportReport {
+            contacts_added: 0,
+            fields_set: 0,
+            conflicts: vec![],
+            invalid: vec![],
+        };
+        let mut applied = vec![];
+        for (node, contact) in imported {
+            if node.is_empty() {
+                report.invalid.extend(
+                    contact
+                        .0
+                        .into_keys()
+                        .map(|field| (String::new(), field, "Contact has no node".to_string())),
+                );
+                continue;
+            }
+            if !self.contacts.0.contains_key(&node) {
+                // adding a new contact cannot fail
+                let operation = contacts::Operation::AddContact(node.clone());
+                applied.push((self.apply(operation).unwrap(), None));
+                report.contacts_added += 1;
+            }
+            let mut fields: Vec<(String, serde_json::Value)> = contact.0.into_iter().collect();
+            fields.sort_by(|(a, _), (b, _)| a.cmp(b));
+            for (field, mut value) in fields {
+                // a list field may hold a single value in the imported format
+                if self.schema.is_list(&field) && !value.is_array() {
+                    value = serde_json::Value::Array(vec![value]);
+                }
+                if let Err(e) = self.schema.validate(&field, &value) {
+                    report.invalid.push((node.clone(), field, e));
+                    continue;
+                }
+                match self.contacts.0.get(&node).and_then(|c| c.0.get(&field)) {
+                    Some(existing) if existing == &value => continue,
+                    Some(existing) => {
+                        report.conflicts.push(contacts::FieldConflict {
+                            node: node.clone(),
+                            field: field.clone(),
+                            existing: existing.to_string(),
+                            imported: value.to_string(),
+                        });
+                        if !overwrite {
+                            continue;
+                        }
+                    }
+                    None => {}
+                }
+                let operation =
+                    contacts::Operation::AddField((node.clone(), field.clone(), value.to_string()));
+                match self.apply(operation) {
+                    Ok(operation_applied) => {
+                        applied.push((operation_applied, None));
+                        report.fields_set += 1;
+                    }
+                    Err(e) => report.invalid.push((node.clone(), field, e)),
+                }
+            }
+        }
+        self.commit(source, applied);
+        report
+    }
+
+    fn subscribe(&mut self, subscriber: Address, capabilities: Option<Vec<String>>) {
+        self.subscriptions.subscribe(subscriber, capabilities);
         self.save();
     }
 
-    fn remove_field(&mut self, node: NodeId, field: String) {
-        if let Some(contact) = self.contacts.0.get_mut(&node) {
-            contact.0.remove(&field);
+    fn unsubscribe(&mut self, subscriber: &Address) {
+        if self.subscriptions.unsubscribe(subscriber) {
+            self.save();
+        }
+    }
+
+    /// The shareable fields of our own contact.
+    fn card(&self) -> Result<Contact, String> {
+        if self.card_fields.is_empty() {
+            return Err("No card shared".to_string());
         }
+        let fields = ReadableFields::Only(self.card_fields.iter().cloned().collect());
+        Ok(self
+            .get_contact(self.our.node.clone())
+            .map(|contact| contact.visible(&fields))
+            .unwrap_or(Contact(HashMap::new())))
+    }
+
+    fn set_card_fields(&mut self, fields: Vec<String>) {
+        self.card_fields = fields.into_iter().collect();
         self.save();
     }
+
+    /// Ask the contacts process of `node` for its card.
+    fn fetch_card(&self, node: &NodeId) -> Result<Contact, String> {
+        if node == &self.our.node {
+            return Err("Cannot fetch our own card".to_string());
+        }
+        let Ok(Ok(response)) = Request::to(Address::new(node, self.our.process.clone()))
+            .body(serde_json::to_vec(&contacts::Request::GetCard).unwrap())
+            .send_and_await_response(CARD_TIMEOUT)
+        else {
+            return Err(format!("{node} did not respond"));
+        };
+        match serde_json::from_slice(response.body()) {
+            Ok(contacts::Response::GetCard) => {}
+            Ok(contacts::Response::Err(e)) => return Err(e),
+            _ => return Err("Malformed card".to_string()),
+        }
+        response
+            .blob()
+            .and_then(|blob| serde_json::from_slice(&blob.bytes).ok())
+            .ok_or_else(|| "Malformed card".to_string())
+    }
+
+    /// Up to `count` current contacts, most recently added first.
+    fn recent_contacts(&self, count: u32) -> Vec<NodeId> {
+        let mut recent: Vec<NodeId> = vec![];
+        for node in self.audit_log.recently_added() {
+            if recent.len() == count as usize {
+                break;
+            }
+            if self.contacts.0.contains_key(node) && !recent.contains(node) {
+                recent.push(node.clone());
+            }
+        }
+        recent
+    }
+
+    fn groups(&self) -> &Groups {
+        &self.groups
+    }
+
+    fn schema(&self) -> &Schema {
+        &self.schema
+    }
+
+    fn schema_mut(&mut self) -> &mut Schema {
+        &mut self.schema
+    }
 }
 
 wit_bindgen::generate!({
@@ -77,16 +561,27 @@ call_init!(initialize);
 fn initialize(our: Address) {
     kiprintln!("started");
 
+    create_drive(our.package_id(), "exports", None).unwrap();
+    let _server = http_api::serve(&our);
     let mut state: ContactsState = ContactsState::new(our);
 
     main_loop(&mut state);
 }
 
+/// Requests only tests may make
+#[cfg(feature = "test")]
+#[derive(Deserialize)]
+enum TestRequest {
+    /// Load the saved state in the request blob, as if we restarted with it
+    LoadState,
+}
+
 fn main_loop(state: &mut ContactsState) {
     loop {
         match await_message() {
-            Err(_send_error) => {
-                // ignore send errors, local-only process
+            Err(send_error) => {
+                // the only requests we send are events: prune subscribers that miss them
+                state.unsubscribe(&send_error.target);
                 continue;
             }
             Ok(Message::Request {
@@ -95,29 +590,54 @@ fn main_loop(state: &mut ContactsState) {
                 capabilities,
                 ..
             }) => {
-                // ignore messages from other nodes -- technically superfluous check
-                // since manifest does not acquire networking capability
-                if source.node() != state.our.node {
+                // other nodes may only fetch our card
+                if source.node() != state.our.node
+                    && !matches!(
+                        serde_json::from_slice(&body),
+                        Ok(contacts::Request::GetCard)
+                    )
+                {
+                    continue;
+                }
+                if source.process == "http-server:distro:sys" {
+                    http_api::handle_http_request(state, &source, &body);
+                    continue;
+                }
+                #[cfg(feature = "test")]
+                if let Ok(TestRequest::LoadState) = serde_json::from_slice(&body) {
+                    set_state(&get_blob().map(|blob| blob.bytes).unwrap_or_default());
+                    *state = ContactsState::new(state.our.clone());
+                    Response::new().body(vec![]).send().unwrap();
                     continue;
                 }
-                handle_request(&body, capabilities, state);
+                handle_request(&source, &body, capabilities, state);
             }
             _ => continue, // ignore responses
         }
     }
 }
 
-fn handle_request(body: &[u8], capabilities: Vec<Capability>, state: &mut ContactsState) {
-    let (response, blob) = handle_contacts_request(state, body, Some(capabilities));
-    let mut response = Response::new().body(serde_json::to_vec(&response).unwrap());
+fn handle_request(
+    source: &Address,
+    body: &[u8],
+    capabilities: Vec<Capability>,
+    state: &mut ContactsState,
+) {
+    let (response, blob) = handle_contacts_request(state, source, body, Some(capabilities));
+    let mut message = Response::new().body(serde_json::to_vec(&response).unwrap());
     if let Some(blob) = blob {
-        response = response.blob(blob);
+        message = message.blob(blob);
+    }
+    // we may attach capabilities we issue: the grantee can save and share them
+    if let contacts::Response::IssueCapability(ref grant) = response {
+        message = message.capabilities(vec![Capability::new(&state.our, Grants::params(grant))]);
     }
-    response.send().unwrap();
+    message.send().unwrap();
 }
 
 fn handle_contacts_request(
     state: &mut ContactsState,
+    source: &Address,
     request_bytes: &[u8],
     capabilities: Option<Vec<Capability>>,
 ) -> (contacts::Response, Option<LazyLoadBlob>) {
@@ -127,30 +647,48 @@ fn handle_contacts_request(
             None,
         );
     };
+    // subscribers are held to the capabilities they subscribed with for as long as they last
+    let subscribed_with: Option<Vec<String>> = capabilities.as_ref().map(|capabilities| {
+        capabilities
+            .iter()
+            .filter(|capability| capability.issuer() == &state.our)
+            .map(|capability| capability.params().to_string())
+            .collect()
+    });
+    // only capabilities we issued are meaningful to us, and only until they expire
+    // or are revoked; none if the request comes from our owner, who may do anything
+    let held: Option<Vec<contacts::Capability>> = capabilities.as_ref().map(|capabilities| {
+        capabilities
+            .iter()
+            .filter(|capability| capability.issuer() == &state.our)
+            .filter_map(|capability| state.grants.check(capability.params()))
+            .collect()
+    });
     // ANCHOR: check_capabilities
-    // each request requires one of read-name-only, read, add, or remove
-    if let Some(capabilities) = capabilities {
-        let required_capability = Capability::new(
-            &state.our,
-            serde_json::to_string(&match request {
-                contacts::Request::GetNames => contacts::Capability::ReadNameOnly,
-                contacts::Request::GetAllContacts | contacts::Request::GetContact(_) => {
-                    contacts::Capability::Read
-                }
-                contacts::Request::AddContact(_) | contacts::Request::AddField(_) => {
-                    contacts::Capability::Add
-                }
-                contacts::Request::RemoveContact(_) | contacts::Request::RemoveField(_) => {
-                    contacts::Capability::Remove
-                }
-            })
-            .unwrap(),
-        );
-        if !capabilities.contains(&required_capability) {
-            return (
-                contacts::Response::Err("Missing capability".to_string()),
-                None,
-            );
+    // each request requires every capability `required_capabilities()` names for it;
+    // reads of contacts may instead use read-fields, limiting the fields returned
+    let mut readable_fields = ReadableFields::All;
+    if let Some(ref held) = held {
+        let required_capabilities = required_capabilities(&request);
+        if !required_capabilities
+            .iter()
+            .all(|capability| held.contains(capability))
+        {
+            let fields: HashSet<String> = held
+                .iter()
+                .filter_map(|capability| match capability {
+                    contacts::Capability::ReadFields(fields) => Some(fields.clone()),
+                    _ => None,
+                })
+                .flatten()
+                .collect();
+            if required_capabilities != [contacts::Capability::Read] || fields.is_empty() {
+                return (
+                    contacts::Response::Err("Missing capability".to_string()),
+                    None,
+                );
+            }
+            readable_fields = ReadableFields::Only(fields);
         }
     }
     // ANCHOR_END: check_capabilities
@@ -167,38 +705,421 @@ fn handle_contacts_request(
             ),
             None,
         ),
+        contacts::Request::GetRecent(count) => (
+            contacts::Response::GetRecent(state.recent_contacts(count)),
+            None,
+        ),
+        // members are always contacts: removing a contact removes it from its groups
+        contacts::Request::GetGroupMembers(group) => match state.groups().members(&group) {
+            Ok(members) => (
+                contacts::Response::GetGroupMembers(members.iter().cloned().collect()),
+                None,
+            ),
+            Err(e) => (contacts::Response::Err(e), None),
+        },
         contacts::Request::GetAllContacts => (
             contacts::Response::GetAllContacts,
             Some(LazyLoadBlob::new(
                 Some("application/json"),
-                serde_json::to_vec(state.contacts()).unwrap(),
+                serde_json::to_vec(&state.visible_contacts(&readable_fields)).unwrap(),
             )),
         ),
         contacts::Request::GetContact(node) => (
             contacts::Response::GetContact,
             Some(LazyLoadBlob::new(
                 Some("application/json"),
-                serde_json::to_vec(&state.get_contact(node)).unwrap(),
+                serde_json::to_vec(
+                    &state
+                        .get_contact(node)
+                        .map(|contact| contact.visible(&readable_fields)),
+                )
+                .unwrap(),
             )),
         ),
-        contacts::Request::AddContact(node) => {
-            state.add_contact(node);
-            (contacts::Response::AddContact, None)
+        contacts::Request::Search(search_request) => {
+            // search only what the caller can read, so hidden fields never match
+            let visible = state.visible_contacts(&readable_fields);
+            let group = match search_request.group {
+                None => None,
+                Some(ref group) => match state.groups().members(group) {
+                    Ok(members) => Some(members),
+                    Err(e) => return (contacts::Response::Err(e), None),
+                },
+            };
+            match search::search(&visible, &search_request, group) {
+                Ok(results) => (
+                    contacts::Response::Search,
+                    Some(LazyLoadBlob::new(
+                        Some("application/json"),
+                        serde_json::to_vec(&results).unwrap(),
+                    )),
+                ),
+                Err(e) => (contacts::Response::Err(e), None),
+            }
         }
-        contacts::Request::AddField((node, field, value)) => {
-            let Ok(value) = serde_json::from_str::<serde_json::Value>(&value) else {
-                return (contacts::Response::Err("Malformed value".to_string()), None);
+        contacts::Request::ExportContacts(export_request) => {
+            let visible = state.visible_contacts(&readable_fields);
+            let (mime, exported) = match export_request.format {
+                contacts::ContactsFormat::Vcard => ("text/vcard", formats::to_vcard(&visible)),
+                contacts::ContactsFormat::Csv => ("text/csv", formats::to_csv(&visible)),
+            };
+            let Some(name) = export_request.name else {
+                return (
+                    contacts::Response::ExportContacts,
+                    Some(LazyLoadBlob::new(Some(mime), exported)),
+                );
             };
-            state.add_field(node, field, value);
-            (contacts::Response::AddField, None)
+            let path = match state.exports_path(&name) {
+                Ok(path) => path,
+                Err(e) => return (contacts::Response::Err(e), None),
+            };
+            if let Err(e) =
+                open_file(&path, true, None).and_then(|file| file.write(exported.as_bytes()))
+            {
+                return (
+                    contacts::Response::Err(format!("Failed to write {path}: {e}")),
+                    None,
+                );
+            }
+            (contacts::Response::ExportContacts, None)
         }
-        contacts::Request::RemoveContact(node) => {
-            state.remove_contact(node);
-            (contacts::Response::RemoveContact, None)
+        contacts::Request::ImportContacts(import_request) => {
+            let bytes = match import_request.name {
+                Some(ref name) => {
+                    let path = match state.exports_path(name) {
+                        Ok(path) => path,
+                        Err(e) => return (contacts::Response::Err(e), None),
+                    };
+                    match open_file(&path, false, None).and_then(|file| file.read()) {
+                        Ok(bytes) => bytes,
+                        Err(e) => {
+                            return (
+                                contacts::Response::Err(format!("Failed to read {path}: {e}")),
+                                None,
+                            )
+                        }
+                    }
+                }
+                None => match get_blob() {
+                    Some(blob) => blob.bytes,
+                    None => return (contacts::Response::Err("Missing blob".to_string()), None),
+                },
+            };
+            let Ok(text) = String::from_utf8(bytes) else {
+                return (
+                    contacts::Response::Err("Import is not UTF-8".to_string()),
+                    None,
+                );
+            };
+            let imported = match import_request.format {
+                contacts::ContactsFormat::Vcard => formats::from_vcard(&text),
+                contacts::ContactsFormat::Csv => formats::from_csv(&text),
+            };
+            match imported {
+                Ok(imported) => (
+                    contacts::Response::ImportContacts(state.import_contacts(
+                        source,
+                        imported,
+                        import_request.overwrite,
+                    )),
+                    None,
+                ),
+                Err(e) => (contacts::Response::Err(e), None),
+            }
+        }
+        contacts::Request::Subscribe => {
+            state.subscribe(source.clone(), subscribed_with);
+            (contacts::Response::Subscribe, None)
+        }
+        contacts::Request::Unsubscribe => {
+            state.unsubscribe(source);
+            (contacts::Response::Unsubscribe, None)
+        }
+        contacts::Request::AddContact(node) => unit_response(
+            state.apply_and_commit(source, contacts::Operation::AddContact(node)),
+            contacts::Response::AddContact,
+        ),
+        contacts::Request::AddField(field) => unit_response(
+            state.apply_and_commit(source, contacts::Operation::AddField(field)),
+            contacts::Response::AddField,
+        ),
+        contacts::Request::RemoveContact(node) => unit_response(
+            state.apply_and_commit(source, contacts::Operation::RemoveContact(node)),
+            contacts::Response::RemoveContact,
+        ),
+        contacts::Request::RemoveField(field) => unit_response(
+            state.apply_and_commit(source, contacts::Operation::RemoveField(field)),
+            contacts::Response::RemoveField,
+        ),
+        contacts::Request::GetGroups => {
+            (contacts::Response::GetGroups(state.groups().names()), None)
+        }
+        contacts::Request::CreateGroup(group) => unit_response(
+            state.apply_and_commit(source, contacts::Operation::CreateGroup(group)),
+            contacts::Response::CreateGroup,
+        ),
+        contacts::Request::RenameGroup(names) => unit_response(
+            state.apply_and_commit(source, contacts::Operation::RenameGroup(names)),
+            contacts::Response::RenameGroup,
+        ),
+        contacts::Request::DeleteGroup(group) => unit_response(
+            state.apply_and_commit(source, contacts::Operation::DeleteGroup(group)),
+            contacts::Response::DeleteGroup,
+        ),
+        contacts::Request::AddToGroup(membership) => unit_response(
+            state.apply_and_commit(source, contacts::Operation::AddToGroup(membership)),
+            contacts::Response::AddToGroup,
+        ),
+        contacts::Request::RemoveFromGroup(membership) => unit_response(
+            state.apply_and_commit(source, contacts::Operation::RemoveFromGroup(membership)),
+            contacts::Response::RemoveFromGroup,
+        ),
+        contacts::Request::History(node) => (
+            contacts::Response::History(
+                state
+                    .audit_log
+                    .history(&node)
+                    .into_iter()
+                    .filter_map(|change| visible_change(change, &readable_fields))
+                    .collect(),
+            ),
+            None,
+        ),
+        contacts::Request::Undo(count) => match state.undo(source, count) {
+            Ok(undone) => (contacts::Response::Undo(undone), None),
+            Err(e) => (contacts::Response::Err(e), None),
+        },
+        contacts::Request::SetHistoryLimit(limit) => {
+            state.audit_log.set_limit(limit);
+            state.save();
+            (contacts::Response::SetHistoryLimit, None)
         }
-        contacts::Request::RemoveField((node, field)) => {
-            state.remove_field(node, field);
-            (contacts::Response::RemoveField, None)
+        contacts::Request::Batch(operations) => unit_response(
+            state.apply_batch(source, operations),
+            contacts::Response::Batch,
+        ),
+        contacts::Request::GetSchema => {
+            (contacts::Response::GetSchema(state.schema().info()), None)
         }
+        contacts::Request::SetFieldSchema(field_schema) => {
+            if let Err(e) = state.schema_mut().set_field(field_schema) {
+                return (contacts::Response::Err(e), None);
+            }
+            state.save();
+            (contacts::Response::SetFieldSchema, None)
+        }
+        contacts::Request::RemoveFieldSchema(name) => {
+            state.schema_mut().remove_field(&name);
+            state.save();
+            (contacts::Response::RemoveFieldSchema, None)
+        }
+        contacts::Request::SetStrict(strict) => {
+            state.schema_mut().set_strict(strict);
+            state.save();
+            (contacts::Response::SetStrict, None)
+        }
+        contacts::Request::IssueCapability(issue_request) => {
+            // manage-grants is only granted through our manifest, never passed on,
+            // and other capabilities are only passed on by those who hold them
+            if issue_request.capability == contacts::Capability::ManageGrants {
+                return (
+                    contacts::Response::Err("Cannot issue manage-grants".to_string()),
+                    None,
+                );
+            }
+            if held
+                .as_ref()
+                .is_some_and(|held| !can_issue(held, &issue_request.capability))
+            {
+                return (
+                    contacts::Response::Err("Cannot issue a capability not held".to_string()),
+                    None,
+                );
+            }
+            let grant =
+                state
+                    .grants
+                    .issue(source, issue_request.capability, issue_request.expires_in);
+            state.save();
+            (contacts::Response::IssueCapability(grant), None)
+        }
+        contacts::Request::GetGrants => (
+            contacts::Response::GetGrants(state.grants.outstanding()),
+            None,
+        ),
+        contacts::Request::RevokeGrant(id) => {
+            let result = state.grants.revoke(id);
+            if result.is_ok() {
+                state.save();
+            }
+            unit_response(result, contacts::Response::RevokeGrant)
+        }
+        contacts::Request::GetCard => match state.card() {
+            Ok(card) => (
+                contacts::Response::GetCard,
+                Some(LazyLoadBlob::new(
+                    Some("application/json"),
+                    serde_json::to_vec(&card).unwrap(),
+                )),
+            ),
+            Err(e) => (contacts::Response::Err(e), None),
+        },
+        contacts::Request::SetCardFields(fields) => {
+            state.set_card_fields(fields);
+            (contacts::Response::SetCardFields, None)
+        }
+        contacts::Request::FetchCard(fetch_request) => {
+            match state.fetch_card(&fetch_request.node) {
+                Ok(card) => (
+                    contacts::Response::FetchCard(state.import_contacts(
+                        source,
+                        vec![(fetch_request.node, card)],
+                        fetch_request.overwrite,
+                    )),
+                    None,
+                ),
+                Err(e) => (contacts::Response::Err(e), None),
+            }
+        }
+    }
+}
+
+fn unit_response(
+    result: Result<(), String>,
+    response: contacts::Response,
+) -> (contacts::Response, Option<LazyLoadBlob>) {
+    match result {
+        Ok(()) => (response, None),
+        Err(e) => (contacts::Response::Err(e), None),
+    }
+}
+
+/// The capabilities a process must hold to make `request`: our owner needs none.
+fn required_capabilities(request: &contacts::Request) -> Vec<contacts::Capability> {
+    match request {
+        contacts::Request::GetNames
+        | contacts::Request::GetGroups
+        | contacts::Request::GetGroupMembers(_)
+        | contacts::Request::GetRecent(_) => vec![contacts::Capability::ReadNameOnly],
+        contacts::Request::GetAllContacts
+        | contacts::Request::GetContact(_)
+        | contacts::Request::Search(_)
+        | contacts::Request::ExportContacts(_)
+        | contacts::Request::Subscribe
+        | contacts::Request::Unsubscribe
+        | contacts::Request::History(_) => vec![contacts::Capability::Read],
+        contacts::Request::AddContact(_)
+        | contacts::Request::AddField(_)
+        | contacts::Request::ImportContacts(_)
+        | contacts::Request::CreateGroup(_)
+        | contacts::Request::RenameGroup(_)
+        | contacts::Request::AddToGroup(_) => vec![contacts::Capability::Add],
+        contacts::Request::RemoveContact(_)
+        | contacts::Request::RemoveField(_)
+        | contacts::Request::DeleteGroup(_)
+        | contacts::Request::RemoveFromGroup(_)
+        | contacts::Request::SetHistoryLimit(_) => vec![contacts::Capability::Remove],
+        contacts::Request::Undo(_) => {
+            vec![contacts::Capability::Add, contacts::Capability::Remove]
+        }
+        // a batch requires the capability of each kind of operation it contains
+        contacts::Request::Batch(operations) => {
+            let mut required = vec![];
+            for operation in operations {
+                let capability = operation_capability(operation);
+                if !required.contains(&capability) {
+                    required.push(capability);
+                }
+            }
+            required
+        }
+        contacts::Request::GetSchema => vec![contacts::Capability::Add],
+        contacts::Request::SetFieldSchema(_)
+        | contacts::Request::RemoveFieldSchema(_)
+        | contacts::Request::SetStrict(_) => vec![contacts::Capability::ManageSchema],
+        contacts::Request::IssueCapability(_)
+        | contacts::Request::GetGrants
+        | contacts::Request::RevokeGrant(_) => vec![contacts::Capability::ManageGrants],
+        // our card is public, to local processes and peers alike
+        contacts::Request::GetCard => vec![],
+        contacts::Request::SetCardFields(_) => vec![contacts::Capability::ManageCard],
+        contacts::Request::FetchCard(_) => vec![contacts::Capability::Add],
+    }
+}
+
+/// Whether a holder of `held` may issue `capability`:
+/// only capabilities it holds, or some of the fields it can read.
+fn can_issue(held: &[contacts::Capability], capability: &contacts::Capability) -> bool {
+    held.iter().any(|holding| match (holding, capability) {
+        (contacts::Capability::Read, contacts::Capability::ReadFields(_)) => true,
+        (contacts::Capability::ReadFields(holding), contacts::Capability::ReadFields(fields)) => {
+            fields.iter().all(|field| holding.contains(field))
+        }
+        _ => holding == capability,
+    })
+}
+
+fn operation_capability(operation: &contacts::Operation) -> contacts::Capability {
+    match operation {
+        contacts::Operation::AddContact(_)
+        | contacts::Operation::AddField(_)
+        | contacts::Operation::CreateGroup(_)
+        | contacts::Operation::RenameGroup(_)
+        | contacts::Operation::AddToGroup(_) => contacts::Capability::Add,
+        contacts::Operation::RemoveContact(_)
+        | contacts::Operation::RemoveField(_)
+        | contacts::Operation::DeleteGroup(_)
+        | contacts::Operation::RemoveFromGroup(_) => contacts::Capability::Remove,
+    }
+}
+
+fn to_json<T: Serialize>(value: &T) -> String {
+    serde_json::to_string(value).unwrap()
+}
+
+/// Operations that set each field of `contact` on `node`
+fn restore_fields(node: &NodeId, contact: &Contact) -> Vec<contacts::Operation> {
+    contact
+        .0
+        .iter()
+        .map(|(field, value)| {
+            contacts::Operation::AddField((node.clone(), field.clone(), value.to_string()))
+        })
+        .collect()
+}
+
+/// Hide changes to fields the caller cannot read, or `None` if the whole change is hidden.
+fn visible_change(
+    mut change: contacts::Change,
+    readable_fields: &ReadableFields,
+) -> Option<contacts::Change> {
+    let ReadableFields::Only(fields) = readable_fields else {
+        return Some(change);
+    };
+    match change.operation {
+        contacts::Operation::AddField((_, ref field, _))
+        | contacts::Operation::RemoveField((_, ref field)) => {
+            if !fields.contains(field) {
+                return None;
+            }
+        }
+        contacts::Operation::AddContact(_) | contacts::Operation::RemoveContact(_) => {
+            let visible = |value: Option<String>| {
+                let contact: Contact = serde_json::from_str(&value?).ok()?;
+                Some(to_json(&contact.visible(readable_fields)))
+            };
+            change.old_value = visible(change.old_value);
+            change.new_value = visible(change.new_value);
+        }
+        _ => {}
     }
+    Some(change)
+}
+
+/// Seconds since the UNIX epoch
+fn now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
 }
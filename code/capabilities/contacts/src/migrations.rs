@@ -0,0 +1,57 @@
+use serde::Serialize;
+use serde_json::Value;
+
+/// Version of the layout `ContactsState` is saved in
+pub const STATE_VERSION: u32 = 1;
+
+/// `MIGRATIONS[i]` upgrades a state saved in version `i` to version `i + 1`
+const MIGRATIONS: [fn(Value) -> Result<Value, String>; STATE_VERSION as usize] = [from_untagged];
+
+/// A state tagged with the version of its layout
+#[derive(Serialize)]
+pub struct Versioned<T> {
+    version: u32,
+    state: T,
+}
+
+impl<T> Versioned<T> {
+    pub fn new(state: T) -> Self {
+        Versioned {
+            version: STATE_VERSION,
+            state,
+        }
+    }
+}
+
+/// Upgrade saved `bytes` to the current layout, one version at a time.
+pub fn upgrade(bytes: &[u8]) -> Result<Value, String> {
+    let saved: Value =
+        serde_json::from_slice(bytes).map_err(|e| format!("Malformed state: {e}"))?;
+    let (version, mut state) = match saved {
+        Value::Object(mut saved) if saved.contains_key("version") => {
+            let version = saved
+                .get("version")
+                .and_then(Value::as_u64)
+                .ok_or("Malformed state version")?;
+            let state = saved.remove("state").ok_or("Missing state")?;
+            (version as usize, state)
+        }
+        // states saved before versioning are not tagged
+        state => (0, state),
+    };
+    if version > MIGRATIONS.len() {
+        return Err(format!(
+            "State version {version} is newer than {STATE_VERSION}"
+        ));
+    }
+    for migration in &MIGRATIONS[version..] {
+        state = migration(state)?;
+    }
+    Ok(state)
+}
+
+/// Version 0 is untagged: every field added to it since then
+/// has a serde default, so its layout is otherwise unchanged.
+fn from_untagged(state: Value) -> Result<Value, String> {
+    Ok(state)
+}
@@ -0,0 +1,132 @@
+use crate::kinode::process::contacts::{
+    Cardinality, FieldFormat, FieldSchema, FieldType, SchemaInfo,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+const MIN_PHONE_DIGITS: usize = 7;
+
+/// Optional declarations of known contact fields that `AddField` values are checked against
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Schema {
+    fields: HashMap<String, FieldSchema>,
+    strict: bool,
+}
+
+impl Schema {
+    pub fn info(&self) -> SchemaInfo {
+        let mut fields: Vec<FieldSchema> = self.fields.values().cloned().collect();
+        fields.sort_by(|a, b| a.name.cmp(&b.name));
+        SchemaInfo {
+            fields,
+            strict: self.strict,
+        }
+    }
+
+    pub fn set_field(&mut self, field: FieldSchema) -> Result<(), String> {
+        if field.format.is_some() && field.field_type != FieldType::Text {
+            return Err(format!(
+                "Field {} has a format, so must have type Text",
+                field.name
+            ));
+        }
+        self.fields.insert(field.name.clone(), field);
+        Ok(())
+    }
+
+    pub fn remove_field(&mut self, name: &str) {
+        self.fields.remove(name);
+    }
+
+    pub fn set_strict(&mut self, strict: bool) {
+        self.strict = strict;
+    }
+
+    pub fn is_list(&self, field: &str) -> bool {
+        self.fields
+            .get(field)
+            .is_some_and(|schema| schema.cardinality == Cardinality::Many)
+    }
+
+    /// Check `value` is allowed for `field`, describing the problem if not.
+    pub fn validate(&self, field: &str, value: &serde_json::Value) -> Result<(), String> {
+        let Some(schema) = self.fields.get(field) else {
+            if self.strict {
+                return Err(format!(
+                    "Unknown field {field}: strict mode only allows declared fields"
+                ));
+            }
+            return Ok(());
+        };
+        match schema.cardinality {
+            Cardinality::One => validate_value(schema, value),
+            Cardinality::Many => {
+                let Some(values) = value.as_array() else {
+                    return Err(format!("Field {field} must be a list, got {value}"));
+                };
+                values
+                    .iter()
+                    .try_for_each(|value| validate_value(schema, value))
+            }
+        }
+    }
+}
+
+fn validate_value(schema: &FieldSchema, value: &serde_json::Value) -> Result<(), String> {
+    let field = &schema.name;
+    match schema.field_type {
+        FieldType::Text => {
+            let Some(text) = value.as_str() else {
+                return Err(format!("Field {field} must be text, got {value}"));
+            };
+            let Some(format) = schema.format else {
+                return Ok(());
+            };
+            let (is_valid, description) = match format {
+                FieldFormat::Email => (is_email(text), "email address"),
+                FieldFormat::Url => (is_url(text), "http(s) URL"),
+                FieldFormat::Phone => (is_phone(text), "phone number"),
+            };
+            if !is_valid {
+                return Err(format!(
+                    "Field {field} must be a valid {description}, got {text:?}"
+                ));
+            }
+            Ok(())
+        }
+        FieldType::Number if value.is_number() => Ok(()),
+        FieldType::Number => Err(format!("Field {field} must be a number, got {value}")),
+        FieldType::Boolean if value.is_boolean() => Ok(()),
+        FieldType::Boolean => Err(format!("Field {field} must be a boolean, got {value}")),
+    }
+}
+
+fn is_email(text: &str) -> bool {
+    let Some((local, domain)) = text.split_once('@') else {
+        return false;
+    };
+    !local.is_empty()
+        && !domain.contains('@')
+        && domain.split('.').count() > 1
+        && domain.split('.').all(|label| !label.is_empty())
+        && !text.chars().any(char::is_whitespace)
+}
+
+fn is_url(text: &str) -> bool {
+    let Some(rest) = text
+        .strip_prefix("https://")
+        .or_else(|| text.strip_prefix("http://"))
+    else {
+        return false;
+    };
+    let host = rest.split(['/', '?', '#']).next().unwrap_or_default();
+    !host.is_empty() && !text.chars().any(char::is_whitespace)
+}
+
+fn is_phone(text: &str) -> bool {
+    let digits = text.strip_prefix('+').unwrap_or(text);
+    digits
+        .chars()
+        .all(|c| c.is_ascii_digit() || matches!(c, ' ' | '-' | '.' | '(' | ')'))
+        && digits.chars().filter(char::is_ascii_digit).count() >= MIN_PHONE_DIGITS
+}
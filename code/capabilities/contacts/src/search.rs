@@ -0,0 +1,130 @@
+use crate::kinode::process::contacts::{SearchCondition, SearchFilter, SearchRequest};
+use crate::{Contact, Contacts};
+use kinode_process_lib::NodeId;
+use serde::Serialize;
+use std::cmp::Ordering;
+use std::collections::BTreeSet;
+
+/// Matching contacts in order: serialized as a JSON list of `[node, contact]` pairs,
+/// since JSON dicts need not keep the order of their keys
+#[derive(Serialize)]
+pub struct SearchResults<'a>(Vec<(&'a NodeId, &'a Contact)>);
+
+/// Search `contacts`, or only those in `group` if given.
+pub fn search<'a>(
+    contacts: &'a Contacts,
+    request: &SearchRequest,
+    group: Option<&BTreeSet<NodeId>>,
+) -> Result<SearchResults<'a>, String> {
+    let filters = request
+        .filters
+        .iter()
+        .map(Filter::new)
+        .collect::<Result<Vec<Filter>, String>>()?;
+
+    let mut results: Vec<(&NodeId, &Contact)> = contacts
+        .0
+        .iter()
+        .filter(|(node, _)| group.map_or(true, |members| members.contains(*node)))
+        .filter(|(_, contact)| filters.iter().all(|filter| filter.matches(contact)))
+        .collect();
+
+    results.sort_by(|(a_node, a), (b_node, b)| match request.sort_by {
+        None => reverse_if(a_node.cmp(b_node), request.descending),
+        Some(ref field) => compare_values(a.0.get(field), b.0.get(field), request.descending)
+            .then_with(|| a_node.cmp(b_node)),
+    });
+    if let Some(limit) = request.limit {
+        results.truncate(limit as usize);
+    }
+    Ok(SearchResults(results))
+}
+
+/// A `SearchFilter` with its condition parsed and lowercased, ready for matching
+struct Filter<'a> {
+    field: &'a str,
+    condition: Condition,
+}
+
+enum Condition {
+    Present,
+    Equals(serde_json::Value),
+    Contains(String),
+    Prefix(String),
+}
+
+impl<'a> Filter<'a> {
+    fn new(filter: &'a SearchFilter) -> Result<Self, String> {
+        let condition = match filter.condition {
+            SearchCondition::Present => Condition::Present,
+            SearchCondition::Equals(ref value) => {
+                let Ok(value) = serde_json::from_str(value) else {
+                    return Err(format!("Malformed value for field {}", filter.field));
+                };
+                Condition::Equals(value)
+            }
+            SearchCondition::Contains(ref text) => Condition::Contains(text.to_lowercase()),
+            SearchCondition::Prefix(ref text) => Condition::Prefix(text.to_lowercase()),
+        };
+        Ok(Filter {
+            field: &filter.field,
+            condition,
+        })
+    }
+
+    fn matches(&self, contact: &Contact) -> bool {
+        let Some(value) = contact.0.get(self.field) else {
+            return false;
+        };
+        // a list field matches if the list itself or any of its values match
+        let mut values = vec![value];
+        if let serde_json::Value::Array(items) = value {
+            values.extend(items);
+        }
+        values.into_iter().any(|value| match self.condition {
+            Condition::Present => true,
+            Condition::Equals(ref expected) => value == expected,
+            Condition::Contains(ref text) => value
+                .as_str()
+                .is_some_and(|value| value.to_lowercase().contains(text)),
+            Condition::Prefix(ref text) => value
+                .as_str()
+                .is_some_and(|value| value.to_lowercase().starts_with(text)),
+        })
+    }
+}
+
+/// Order field values: missing values last, numbers numerically, and anything else by its text.
+fn compare_values(
+    a: Option<&serde_json::Value>,
+    b: Option<&serde_json::Value>,
+    descending: bool,
+) -> Ordering {
+    match (a, b) {
+        (None, None) => Ordering::Equal,
+        (None, Some(_)) => Ordering::Greater,
+        (Some(_), None) => Ordering::Less,
+        (Some(a), Some(b)) => reverse_if(
+            match (a.as_f64(), b.as_f64()) {
+                (Some(a), Some(b)) => a.total_cmp(&b),
+                _ => sort_text(a).cmp(&sort_text(b)),
+            },
+            descending,
+        ),
+    }
+}
+
+fn reverse_if(ordering: Ordering, reverse: bool) -> Ordering {
+    if reverse {
+        ordering.reverse()
+    } else {
+        ordering
+    }
+}
+
+fn sort_text(value: &serde_json::Value) -> String {
+    match value.as_str() {
+        Some(text) => text.to_lowercase(),
+        None => value.to_string(),
+    }
+}
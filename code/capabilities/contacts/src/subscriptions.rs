@@ -0,0 +1,87 @@
+use crate::grants::Grants;
+use crate::kinode::process::contacts::{Capability, ContactEvent};
+use crate::ReadableFields;
+use kinode_process_lib::{Address, Request};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+
+/// Seconds a subscriber has to respond to an event before it is unsubscribed
+const EVENT_TIMEOUT: u64 = 5;
+
+/// Processes to notify of changes, each with the params of the capabilities
+/// it subscribed with, or `None` if it subscribed on behalf of the node owner
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Subscriptions(HashMap<Address, Option<Vec<String>>>);
+
+impl Subscriptions {
+    pub fn subscribe(&mut self, subscriber: Address, capabilities: Option<Vec<String>>) {
+        self.0.insert(subscriber, capabilities);
+    }
+
+    pub fn unsubscribe(&mut self, subscriber: &Address) -> bool {
+        self.0.remove(subscriber).is_some()
+    }
+
+    /// Send `event` to every subscriber that can read the field it concerns,
+    /// checking their capabilities again first: subscribers whose capabilities
+    /// have all expired or been revoked are dropped, and `true` returned.
+    /// Subscribers that do not respond come back to us as a `SendError`.
+    pub fn publish(&mut self, grants: &Grants, event: &ContactEvent) -> bool {
+        let field = match event {
+            ContactEvent::ContactAdded(_) | ContactEvent::ContactRemoved(_) => None,
+            ContactEvent::FieldSet((_, field, _)) | ContactEvent::FieldRemoved((_, field)) => {
+                Some(field)
+            }
+        };
+        let body = serde_json::to_vec(event).unwrap();
+        let before = self.0.len();
+        let mut subscribers = vec![];
+        self.0.retain(|subscriber, capabilities| {
+            let fields = match capabilities {
+                None => ReadableFields::All,
+                Some(capabilities) => match readable_fields(grants, capabilities) {
+                    Some(fields) => fields,
+                    None => return false,
+                },
+            };
+            subscribers.push((subscriber.clone(), fields));
+            true
+        });
+        for (subscriber, fields) in subscribers.iter() {
+            if let (Some(field), ReadableFields::Only(fields)) = (field, fields) {
+                if !fields.contains(field) {
+                    continue;
+                }
+            }
+            Request::to(subscriber)
+                .body(body.clone())
+                .expects_response(EVENT_TIMEOUT)
+                .send()
+                .unwrap();
+        }
+        self.0.len() != before
+    }
+}
+
+/// The fields the capabilities with `params` still let a subscriber read, if any.
+fn readable_fields(grants: &Grants, params: &[String]) -> Option<ReadableFields> {
+    let held: Vec<Capability> = params
+        .iter()
+        .filter_map(|params| grants.check(params))
+        .collect();
+    if held.contains(&Capability::Read) {
+        return Some(ReadableFields::All);
+    }
+    let fields: HashSet<String> = held
+        .into_iter()
+        .filter_map(|capability| match capability {
+            Capability::ReadFields(fields) => Some(fields),
+            _ => None,
+        })
+        .flatten()
+        .collect();
+    if fields.is_empty() {
+        return None;
+    }
+    Some(ReadableFields::Only(fields))
+}
@@ -0,0 +1,385 @@
+use crate::kinode::process::contacts;
+use crate::kinode::process::tester::{
+    FailResponse, Request as TesterRequest, Response as TesterResponse, RunRequest,
+};
+use kinode_process_lib::{
+    await_message, call_init, println, save_capabilities, Address, Capability, Message, Request,
+    Response,
+};
+use serde_json::{json, Map, Value};
+
+mod tester_lib;
+
+wit_bindgen::generate!({
+    path: "target/wit",
+    world: "capabilities-test-test-doria-dot-kino-v0",
+    generate_unused_types: true,
+    additional_derives: [PartialEq, serde::Deserialize, serde::Serialize, process_macros::SerdeJsonInto],
+});
+
+const CAROL: &str = "carol.os";
+const DAVE: &str = "dave.os";
+const ERIN: &str = "erin.os";
+const FRANK: &str = "frank.os";
+
+#[derive(Debug, serde::Deserialize, serde::Serialize, process_macros::SerdeJsonInto)]
+enum TestRequest {
+    LoadState,
+}
+
+fn make_cap(contacts_process: &Address, capability: contacts::Capability) -> Capability {
+    Capability::new(
+        contacts_process,
+        serde_json::to_string(&capability).unwrap(),
+    )
+}
+
+/// Make `request` of contacts with `capabilities` attached, returning its Response
+/// and the Message carrying it, whose blob and capabilities some Responses use.
+fn contacts_request(
+    contacts_process: &Address,
+    request: contacts::Request,
+    capabilities: Vec<Capability>,
+) -> anyhow::Result<(contacts::Response, Message)> {
+    let message = Request::to(contacts_process)
+        .body(request)
+        .capabilities(capabilities)
+        .send_and_await_response(5)??;
+    Ok((message.body().try_into()?, message))
+}
+
+/// The fields of contact `node` that `capabilities` can read, or none if refused.
+fn get_contact(
+    contacts_process: &Address,
+    node: &str,
+    capabilities: Vec<Capability>,
+) -> anyhow::Result<Option<Map<String, Value>>> {
+    let (response, message) = contacts_request(
+        contacts_process,
+        contacts::Request::GetContact(node.to_string()),
+        capabilities,
+    )?;
+    if response != contacts::Response::GetContact {
+        return Ok(None);
+    }
+    let Some(blob) = message.blob() else {
+        return Err(anyhow::anyhow!("got no contact in blob"));
+    };
+    Ok(Some(serde_json::from_slice(blob.bytes())?))
+}
+
+/// Nodes of the contacts with a rank, in the order of their ranks.
+fn search_ranked(
+    contacts_process: &Address,
+    descending: bool,
+    limit: Option<u32>,
+    read_cap: &Capability,
+) -> anyhow::Result<Vec<String>> {
+    let (response, message) = contacts_request(
+        contacts_process,
+        contacts::Request::Search(contacts::SearchRequest {
+            filters: vec![contacts::SearchFilter {
+                field: "rank".to_string(),
+                condition: contacts::SearchCondition::Present,
+            }],
+            group: None,
+            sort_by: Some("rank".to_string()),
+            descending,
+            limit,
+        }),
+        vec![read_cap.clone()],
+    )?;
+    if response != contacts::Response::Search {
+        return Err(anyhow::anyhow!(
+            "unexpected Response to Search: {response:?}"
+        ));
+    }
+    let Some(blob) = message.blob() else {
+        return Err(anyhow::anyhow!("got no results in blob"));
+    };
+    let results: Vec<(String, Value)> = serde_json::from_slice(blob.bytes())?;
+    Ok(results.into_iter().map(|(node, _)| node).collect())
+}
+
+/// Have contacts load `state` as if it had been saved before a restart.
+fn load_state(contacts_process: &Address, state: &Value) -> anyhow::Result<()> {
+    Request::to(contacts_process)
+        .body(TestRequest::LoadState)
+        .blob_bytes(serde_json::to_vec(state)?)
+        .send_and_await_response(5)??;
+    Ok(())
+}
+
+fn run_test(our: &Address) -> anyhow::Result<()> {
+    let message = await_message()?;
+
+    if !message.is_request() {
+        fail!("capabilities-test-test");
+    }
+    let source = message.source();
+    if our.node != source.node {
+        return Err(anyhow::anyhow!(
+            "rejecting foreign Message from {:?}",
+            source,
+        ));
+    }
+    let TesterRequest::Run(RunRequest { .. }) = message.body().try_into()?;
+
+    let contacts_process =
+        Address::from((our.node(), "contacts", "capabilities-test", "doria.kino"));
+    let read_cap = make_cap(&contacts_process, contacts::Capability::Read);
+    let read_email_cap = make_cap(
+        &contacts_process,
+        contacts::Capability::ReadFields(vec!["email".to_string()]),
+    );
+    let add_cap = make_cap(&contacts_process, contacts::Capability::Add);
+    let remove_cap = make_cap(&contacts_process, contacts::Capability::Remove);
+    let manage_schema_cap = make_cap(&contacts_process, contacts::Capability::ManageSchema);
+    let manage_grants_cap = make_cap(&contacts_process, contacts::Capability::ManageGrants);
+
+    // requests without the capability they require are refused
+    let (response, _) = contacts_request(
+        &contacts_process,
+        contacts::Request::AddContact(CAROL.to_string()),
+        vec![read_cap.clone()],
+    )?;
+    if !matches!(response, contacts::Response::Err(_)) {
+        fail!("capabilities-test-test");
+    }
+
+    // add contacts to test on
+    let mut requests: Vec<contacts::Request> = [CAROL, DAVE, ERIN]
+        .into_iter()
+        .map(|node| contacts::Request::AddContact(node.to_string()))
+        .collect();
+    for (node, field, value) in [
+        (CAROL, "email", "\"carol@doria.kino\""),
+        (CAROL, "phone", "\"555-0100\""),
+        (CAROL, "rank", "3"),
+        (DAVE, "rank", "1"),
+        (ERIN, "rank", "2"),
+    ] {
+        requests.push(contacts::Request::AddField((
+            node.to_string(),
+            field.to_string(),
+            value.to_string(),
+        )));
+    }
+    for request in requests {
+        let (response, _) = contacts_request(&contacts_process, request, vec![add_cap.clone()])?;
+        if matches!(response, contacts::Response::Err(_)) {
+            fail!("capabilities-test-test");
+        }
+    }
+
+    // read-fields reads only the fields it names
+    let Some(contact) = get_contact(&contacts_process, CAROL, vec![read_email_cap.clone()])? else {
+        fail!("capabilities-test-test");
+    };
+    if contact.get("email") != Some(&json!("carol@doria.kino"))
+        || contact.contains_key("phone")
+        || contact.contains_key("rank")
+    {
+        fail!("capabilities-test-test");
+    }
+
+    // values that do not match the schema of their field are rejected
+    let (response, _) = contacts_request(
+        &contacts_process,
+        contacts::Request::SetFieldSchema(contacts::FieldSchema {
+            name: "email".to_string(),
+            field_type: contacts::FieldType::Text,
+            format: Some(contacts::FieldFormat::Email),
+            cardinality: contacts::Cardinality::One,
+        }),
+        vec![manage_schema_cap],
+    )?;
+    if response != contacts::Response::SetFieldSchema {
+        fail!("capabilities-test-test");
+    }
+    let (response, _) = contacts_request(
+        &contacts_process,
+        contacts::Request::AddField((
+            CAROL.to_string(),
+            "email".to_string(),
+            "\"not an email\"".to_string(),
+        )),
+        vec![add_cap.clone()],
+    )?;
+    if !matches!(response, contacts::Response::Err(_)) {
+        fail!("capabilities-test-test");
+    }
+    let Some(contact) = get_contact(&contacts_process, CAROL, vec![read_cap.clone()])? else {
+        fail!("capabilities-test-test");
+    };
+    if contact.get("email") != Some(&json!("carol@doria.kino")) {
+        fail!("capabilities-test-test");
+    }
+
+    // search results keep the order they are sorted in
+    if search_ranked(&contacts_process, false, None, &read_cap)? != [DAVE, ERIN, CAROL] {
+        fail!("capabilities-test-test");
+    }
+    if search_ranked(&contacts_process, true, Some(2), &read_cap)? != [CAROL, ERIN] {
+        fail!("capabilities-test-test");
+    }
+
+    // a batch that fails part way applies nothing
+    let (response, _) = contacts_request(
+        &contacts_process,
+        contacts::Request::Batch(vec![
+            contacts::Operation::AddField((
+                CAROL.to_string(),
+                "nickname".to_string(),
+                "\"caz\"".to_string(),
+            )),
+            contacts::Operation::AddToGroup(("no-such-group".to_string(), CAROL.to_string())),
+        ]),
+        vec![add_cap.clone()],
+    )?;
+    if !matches!(response, contacts::Response::Err(_)) {
+        fail!("capabilities-test-test");
+    }
+    let Some(contact) = get_contact(&contacts_process, CAROL, vec![read_cap.clone()])? else {
+        fail!("capabilities-test-test");
+    };
+    if contact.contains_key("nickname") {
+        fail!("capabilities-test-test");
+    }
+
+    // undo reverts the last change
+    let (response, _) = contacts_request(
+        &contacts_process,
+        contacts::Request::RemoveField((CAROL.to_string(), "phone".to_string())),
+        vec![remove_cap.clone()],
+    )?;
+    if response != contacts::Response::RemoveField {
+        fail!("capabilities-test-test");
+    }
+    let (response, _) = contacts_request(
+        &contacts_process,
+        contacts::Request::Undo(1),
+        vec![add_cap.clone(), remove_cap],
+    )?;
+    if response != contacts::Response::Undo(1) {
+        fail!("capabilities-test-test");
+    }
+    let Some(contact) = get_contact(&contacts_process, CAROL, vec![read_cap.clone()])? else {
+        fail!("capabilities-test-test");
+    };
+    if contact.get("phone") != Some(&json!("555-0100")) {
+        fail!("capabilities-test-test");
+    }
+
+    // issued capabilities work until they are revoked
+    let (response, message) = contacts_request(
+        &contacts_process,
+        contacts::Request::IssueCapability(contacts::IssueRequest {
+            capability: contacts::Capability::Read,
+            expires_in: Some(60),
+        }),
+        vec![manage_grants_cap.clone(), read_cap.clone()],
+    )?;
+    let contacts::Response::IssueCapability(grant) = response else {
+        fail!("capabilities-test-test");
+    };
+    let Some(issued_cap) = message.capabilities().first().cloned() else {
+        fail!("capabilities-test-test");
+    };
+    save_capabilities(message.capabilities());
+    if get_contact(&contacts_process, CAROL, vec![issued_cap.clone()])?.is_none() {
+        fail!("capabilities-test-test");
+    }
+    let (response, _) = contacts_request(
+        &contacts_process,
+        contacts::Request::RevokeGrant(grant.id),
+        vec![manage_grants_cap.clone()],
+    )?;
+    if response != contacts::Response::RevokeGrant {
+        fail!("capabilities-test-test");
+    }
+    if get_contact(&contacts_process, CAROL, vec![issued_cap])?.is_some() {
+        fail!("capabilities-test-test");
+    }
+
+    // and until they expire
+    let (response, message) = contacts_request(
+        &contacts_process,
+        contacts::Request::IssueCapability(contacts::IssueRequest {
+            capability: contacts::Capability::Read,
+            expires_in: Some(0),
+        }),
+        vec![manage_grants_cap.clone(), read_cap.clone()],
+    )?;
+    if !matches!(response, contacts::Response::IssueCapability(_)) {
+        fail!("capabilities-test-test");
+    }
+    let Some(expired_cap) = message.capabilities().first().cloned() else {
+        fail!("capabilities-test-test");
+    };
+    save_capabilities(message.capabilities());
+    if get_contact(&contacts_process, CAROL, vec![expired_cap])?.is_some() {
+        fail!("capabilities-test-test");
+    }
+
+    // manage-grants is never issued, nor capabilities the requester does not hold
+    for capability in [
+        contacts::Capability::ManageGrants,
+        contacts::Capability::Add,
+    ] {
+        let (response, _) = contacts_request(
+            &contacts_process,
+            contacts::Request::IssueCapability(contacts::IssueRequest {
+                capability,
+                expires_in: None,
+            }),
+            vec![manage_grants_cap.clone(), read_cap.clone()],
+        )?;
+        if !matches!(response, contacts::Response::Err(_)) {
+            fail!("capabilities-test-test");
+        }
+    }
+
+    // states saved before versioning are migrated
+    load_state(
+        &contacts_process,
+        &json!({
+            "our": contacts_process,
+            "contacts": { FRANK: { "email": "frank@doria.kino" } },
+        }),
+    )?;
+    let Some(contact) = get_contact(&contacts_process, FRANK, vec![read_cap.clone()])? else {
+        fail!("capabilities-test-test");
+    };
+    if contact.get("email") != Some(&json!("frank@doria.kino")) {
+        fail!("capabilities-test-test");
+    }
+    if get_contact(&contacts_process, CAROL, vec![read_cap.clone()])?.is_some() {
+        fail!("capabilities-test-test");
+    }
+
+    // states that cannot be loaded are backed up, and contacts start afresh
+    load_state(
+        &contacts_process,
+        &json!({ "version": u32::MAX, "state": {} }),
+    )?;
+    if get_contact(&contacts_process, FRANK, vec![read_cap])?.is_some() {
+        fail!("capabilities-test-test");
+    }
+
+    Response::new().body(TesterResponse::Run(Ok(()))).send()?;
+    Ok(())
+}
+
+call_init!(init);
+fn init(our: Address) {
+    println!("begin");
+
+    match run_test(&our) {
+        Ok(()) => {}
+        Err(e) => {
+            println!("capabilities-test-test: error: {e:?}");
+            fail!("capabilities-test-test");
+        }
+    };
+}
@@ -0,0 +1,56 @@
+use crate::kinode::process::chat_with_file_transfer::{
+    Request as ChatRequest, Response as ChatResponse, SendFileRequest,
+};
+use kinode_process_lib::{await_next_message_body, call_init, println, Address, Message, Request};
+
+wit_bindgen::generate!({
+    path: "target/wit",
+    world: "chat-with-file-transfer-template-dot-os-v0",
+    generate_unused_types: true,
+    additional_derives: [serde::Deserialize, serde::Serialize],
+});
+
+call_init!(init);
+fn init(our: Address) {
+    let Ok(body) = await_next_message_body() else {
+        println!("failed to get args!");
+        return;
+    };
+
+    let args = String::from_utf8(body).unwrap_or_default();
+    let mut args = args.splitn(3, ' ');
+
+    let (Some(target), Some(name)) = (args.next(), args.next()) else {
+        println!("usage:\nattach:chat-with-file-transfer:template.os target file_name [message]");
+        return;
+    };
+    let message = args.next().unwrap_or_default();
+
+    let Ok(Ok(Message::Response { body, .. })) = Request::to((
+        our.node(),
+        (
+            "chat-with-file-transfer",
+            "chat-with-file-transfer",
+            "template.os",
+        ),
+    ))
+    .body(
+        serde_json::to_vec(&ChatRequest::SendFile(SendFileRequest {
+            target: target.into(),
+            name: name.into(),
+            message: message.into(),
+            encrypt: true,
+        }))
+        .unwrap(),
+    )
+    .send_and_await_response(15) else {
+        println!("did not receive expected Response from chat-with-file-transfer:chat-with-file-transfer:template.os");
+        return;
+    };
+
+    match serde_json::from_slice(&body) {
+        Ok(ChatResponse::SendFile(Ok(()))) => {}
+        Ok(ChatResponse::SendFile(Err(e))) => println!("attach failed: {e}"),
+        _ => println!("did not receive expected SendFile from chat-with-file-transfer:chat-with-file-transfer:template.os"),
+    }
+}
@@ -0,0 +1,181 @@
+//! Message-level signing and encryption of payloads sent between chat processes.
+//!
+//! Each node has an ed25519 key that signs everything it sends to peers
+//! and an x25519 key that peers encrypt payloads to.
+//! Encryption keys come from a static-static Diffie-Hellman exchange,
+//! so the payload is encrypted to the recipient and authenticated to the sender.
+use std::collections::HashMap;
+
+use chacha20poly1305::{aead::Aead, ChaCha20Poly1305, KeyInit, Nonce};
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use hkdf::Hkdf;
+use kinode_process_lib::{get_typed_state, set_state};
+use rand::{rngs::OsRng, RngCore};
+use sha2::Sha256;
+use x25519_dalek::{PublicKey, StaticSecret};
+
+use crate::kinode::process::chat_with_file_transfer::{PublicKeys, Sealed};
+
+const KDF_INFO: &[u8] = b"chat-with-file-transfer:template.os payload key";
+const NONCE_LENGTH: usize = 12;
+
+/// Our secret keys: kept in process state so they survive restarts
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct Keys {
+    signing: [u8; 32],
+    encryption: [u8; 32],
+}
+
+/// Process state as loaded: pins are missing from states saved before they were kept
+#[derive(serde::Deserialize)]
+struct SavedState {
+    #[serde(flatten)]
+    keys: Keys,
+    #[serde(default)]
+    peer_keys: HashMap<String, PublicKeys>,
+}
+
+/// Process state as saved: our keys next to the peer keys pinned on first contact
+#[derive(serde::Serialize)]
+struct SavedStateRef<'a> {
+    #[serde(flatten)]
+    keys: &'a Keys,
+    peer_keys: &'a HashMap<String, PublicKeys>,
+}
+
+impl Keys {
+    /// Load our keys and the peer keys we have pinned, generating our keys on first run.
+    pub fn load_or_generate() -> (Self, HashMap<String, PublicKeys>) {
+        if let Some(SavedState { keys, peer_keys }) =
+            get_typed_state(|bytes| serde_json::from_slice(bytes))
+        {
+            return (keys, peer_keys);
+        }
+        let mut keys = Keys {
+            signing: [0; 32],
+            encryption: [0; 32],
+        };
+        OsRng.fill_bytes(&mut keys.signing);
+        OsRng.fill_bytes(&mut keys.encryption);
+        let peer_keys = HashMap::new();
+        keys.save(&peer_keys);
+        (keys, peer_keys)
+    }
+
+    /// Save our keys along with `peer_keys`, so pins survive restarts too.
+    pub fn save(&self, peer_keys: &HashMap<String, PublicKeys>) {
+        let state = SavedStateRef {
+            keys: self,
+            peer_keys,
+        };
+        set_state(&serde_json::to_vec(&state).expect("failed to serialize keys"));
+    }
+
+    pub fn public(&self) -> PublicKeys {
+        let signing = SigningKey::from_bytes(&self.signing).verifying_key();
+        let encryption = PublicKey::from(&StaticSecret::from(self.encryption));
+        PublicKeys {
+            signing: to_hex(signing.as_bytes()),
+            encryption: to_hex(encryption.as_bytes()),
+        }
+    }
+
+    fn cipher(&self, their_keys: &PublicKeys) -> anyhow::Result<ChaCha20Poly1305> {
+        let their_public = PublicKey::from(from_hex_32(&their_keys.encryption)?);
+        let shared = StaticSecret::from(self.encryption).diffie_hellman(&their_public);
+        let mut key = [0; 32];
+        Hkdf::<Sha256>::new(None, shared.as_bytes())
+            .expand(KDF_INFO, &mut key)
+            .map_err(|_| anyhow::anyhow!("failed to derive payload key"))?;
+        Ok(ChaCha20Poly1305::new(&key.into()))
+    }
+
+    /// Sign `payload` from `sender` for `recipient`, encrypting it to `recipient_keys` if given.
+    pub fn seal<T: serde::Serialize>(
+        &self,
+        sender: &str,
+        recipient: &str,
+        recipient_keys: Option<&PublicKeys>,
+        payload: &T,
+    ) -> anyhow::Result<Sealed> {
+        let payload = serde_json::to_vec(payload)?;
+        let (payload, nonce) = match recipient_keys {
+            None => (payload, None),
+            Some(recipient_keys) => {
+                let mut nonce = [0; NONCE_LENGTH];
+                OsRng.fill_bytes(&mut nonce);
+                let ciphertext = self
+                    .cipher(recipient_keys)?
+                    .encrypt(Nonce::from_slice(&nonce), payload.as_slice())
+                    .map_err(|_| anyhow::anyhow!("failed to encrypt payload"))?;
+                (ciphertext, Some(nonce.to_vec()))
+            }
+        };
+        let signature = SigningKey::from_bytes(&self.signing)
+            .sign(&signed_bytes(sender, recipient, &nonce, &payload))
+            .to_bytes()
+            .to_vec();
+        Ok(Sealed {
+            sender_keys: self.public(),
+            payload,
+            nonce,
+            signature,
+        })
+    }
+
+    /// Verify `sealed` was signed by `sender` for us, decrypting it if needed.
+    pub fn open<T: serde::de::DeserializeOwned>(
+        &self,
+        sender: &str,
+        our_node: &str,
+        sealed: &Sealed,
+    ) -> anyhow::Result<T> {
+        let signing = VerifyingKey::from_bytes(&from_hex_32(&sealed.sender_keys.signing)?)?;
+        let signature = Signature::from_slice(&sealed.signature)?;
+        signing.verify(
+            &signed_bytes(sender, our_node, &sealed.nonce, &sealed.payload),
+            &signature,
+        )?;
+        let payload = match sealed.nonce {
+            None => sealed.payload.clone(),
+            Some(ref nonce) => {
+                if nonce.len() != NONCE_LENGTH {
+                    return Err(anyhow::anyhow!("bad nonce length {}", nonce.len()));
+                }
+                self.cipher(&sealed.sender_keys)?
+                    .decrypt(Nonce::from_slice(nonce), sealed.payload.as_slice())
+                    .map_err(|_| anyhow::anyhow!("failed to decrypt payload"))?
+            }
+        };
+        Ok(serde_json::from_slice(&payload)?)
+    }
+}
+
+/// Bind the signature to both nodes so a payload cannot be replayed to another node,
+/// nor passed off as sent by another node holding the same keys.
+fn signed_bytes(sender: &str, recipient: &str, nonce: &Option<Vec<u8>>, payload: &[u8]) -> Vec<u8> {
+    let mut bytes = sender.as_bytes().to_vec();
+    bytes.push(0);
+    bytes.extend_from_slice(recipient.as_bytes());
+    bytes.push(0);
+    if let Some(ref nonce) = nonce {
+        bytes.extend_from_slice(nonce);
+    }
+    bytes.extend_from_slice(payload);
+    bytes
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+fn from_hex_32(hex: &str) -> anyhow::Result<[u8; 32]> {
+    if hex.len() != 64 || !hex.is_ascii() {
+        return Err(anyhow::anyhow!("expected 32 hex-encoded bytes"));
+    }
+    let mut bytes = [0; 32];
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[2 * i..2 * i + 2], 16)?;
+    }
+    Ok(bytes)
+}
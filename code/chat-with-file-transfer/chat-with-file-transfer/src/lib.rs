@@ -1,7 +1,10 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
 use crate::kinode::process::chat_with_file_transfer::{
-    ChatMessage, Request as ChatRequest, Response as ChatResponse, SendRequest,
+    AcceptFileRequest, ChatControl, ChatMessage, ControlAction, DeleteRequest, EditRequest,
+    ExportFormat, ExportRequest, FileAttachment, HistoryResponse, InboundPolicy, PolicyInfo,
+    Presence, PublicKeys, ReactRequest, Reaction, Rejection, Request as ChatRequest,
+    Response as ChatResponse, Sealed, SendFileRequest, SendRequest, TransferStatus,
 };
 use crate::kinode::process::file_transfer_worker::{
     start_download, DownloadRequest, ProgressRequest, Request as WorkerRequest,
@@ -9,9 +12,9 @@ use crate::kinode::process::file_transfer_worker::{
 };
 use crate::kinode::process::standard::{Address as WitAddress, ProcessId as WitProcessId};
 use kinode_process_lib::{
-    await_message, call_init, get_capability, println,
-    vfs::{create_drive, open_file},
-    Address, Message, ProcessId, Request, Response,
+    await_message, call_init, get_blob, get_capability, http, println, timer,
+    vfs::{self, create_drive, open_file},
+    Address, LazyLoadBlob, Message, ProcessId, Request, Response, SendError,
 };
 
 wit_bindgen::generate!({
@@ -21,6 +24,8 @@ wit_bindgen::generate!({
     additional_derives: [serde::Deserialize, serde::Serialize, process_macros::SerdeJsonInto],
 });
 
+mod crypto;
+
 #[derive(Debug, serde::Deserialize, serde::Serialize, process_macros::SerdeJsonInto)]
 #[serde(untagged)] // untagged as a meta-type for all incoming messages
 enum Msg {
@@ -29,6 +34,7 @@ enum Msg {
     WorkerRequest(WorkerRequest),
 
     // responses
+    ChatResponse(ChatResponse),
     WorkerResponse(WorkerResponse),
 }
 
@@ -53,97 +59,1088 @@ impl From<ProcessId> for WitProcessId {
 
 type MessageArchive = HashMap<String, Vec<ChatMessage>>;
 
+/// (node offering the file, file name)
+type DownloadKey = (String, String);
+
+struct Download {
+    /// id of the message carrying the file
+    id: String,
+    /// progress reports so far, to tell a stalled download from a slow one
+    updates: u64,
+}
+
+struct State {
+    message_archive: MessageArchive,
+    /// downloads in progress: at most one per file name, as workers report progress by name
+    downloads: HashMap<DownloadKey, Download>,
+    /// open WebSocket connections from our browser(s)
+    ws_channels: HashSet<u32>,
+    policy: InboundPolicy,
+    blocked: HashSet<String>,
+    approved: HashSet<String>,
+    awaiting_approval: HashSet<String>,
+    /// node -> (start of current rate limit window in seconds, requests in window)
+    request_counts: HashMap<String, (u64, u32)>,
+    /// node -> id of their latest message we have sent a read receipt for
+    read_sent: HashMap<String, String>,
+    /// node -> id of our latest message they have read
+    read_by_peer: HashMap<String, String>,
+    keys: crypto::Keys,
+    /// node -> public keys pinned on first contact; saved with `keys`
+    peer_keys: HashMap<String, PublicKeys>,
+}
+
+impl State {
+    fn new() -> Self {
+        let (keys, peer_keys) = crypto::Keys::load_or_generate();
+        State {
+            message_archive: HashMap::new(),
+            downloads: HashMap::new(),
+            ws_channels: HashSet::new(),
+            policy: InboundPolicy {
+                require_approval: false,
+                rate_limit: DEFAULT_RATE_LIMIT,
+                rate_limit_window_secs: DEFAULT_RATE_LIMIT_WINDOW_SECS,
+            },
+            blocked: HashSet::new(),
+            approved: HashSet::new(),
+            awaiting_approval: HashSet::new(),
+            request_counts: HashMap::new(),
+            read_sent: HashMap::new(),
+            read_by_peer: HashMap::new(),
+            keys,
+            peer_keys,
+        }
+    }
+}
+
+/// One line of a JSON Lines export
+#[derive(Debug, serde::Deserialize, serde::Serialize)]
+struct ArchiveLine {
+    /// node the conversation is with
+    node: String,
+    message: ChatMessage,
+}
+
+/// Pushed to connected browsers whenever a conversation changes
+#[derive(Debug, serde::Serialize)]
+enum WsUpdate {
+    /// a new message, or a new version of an existing one (matched by id)
+    Message { node: String, message: ChatMessage },
+    /// `node` is typing to us
+    Typing { node: String },
+    /// `node` has read our messages up to and including `id`
+    Read { node: String, id: String },
+}
+
+const TIMEOUT_SECS: u64 = 5;
+/// a download that reports no progress for this long is marked failed
+const TRANSFER_TIMEOUT_SECS: u64 = 30;
+const DEFAULT_RATE_LIMIT: u32 = 30;
+const DEFAULT_RATE_LIMIT_WINDOW_SECS: u64 = 60;
+/// further nodes are still refused, but not listed until some are approved or blocked
+const MAX_AWAITING_APPROVAL: usize = 100;
+const UI_PATH: &str = "ui/index.html";
+const CONVERSATIONS_PATH: &str = "/api/conversations";
+const CHAT_PATH: &str = "/api/chat";
+const FILES_PATH: &str = "/api/files";
+const WS_PATH: &str = "/updates";
+
+fn make_chat_address(node: &str) -> Address {
+    Address {
+        node: node.to_string(),
+        process: ProcessId::new(
+            Some("chat-with-file-transfer"),
+            "chat-with-file-transfer",
+            "template.os",
+        ),
+    }
+}
+
+fn make_message_id(our: &Address) -> String {
+    let nanos = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or_default();
+    format!("{}-{nanos}", our.node)
+}
+
+/// When a message was sent, in nanoseconds, as recorded in its id by its sender.
+fn message_time(id: &str) -> u128 {
+    id.rsplit_once('-')
+        .and_then(|(_, nanos)| nanos.parse().ok())
+        .unwrap_or_default()
+}
+
+fn now_secs() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+fn sorted(nodes: &HashSet<String>) -> Vec<String> {
+    let mut nodes: Vec<String> = nodes.iter().cloned().collect();
+    nodes.sort();
+    nodes
+}
+
+/// Decide whether to serve a request from peer `node` according to our inbound policy.
+/// Requests that do not need approval are still held to blocking and the rate limit.
+fn check_inbound(state: &mut State, node: &str, needs_approval: bool) -> Result<(), Rejection> {
+    if state.blocked.contains(node) {
+        return Err(Rejection::Blocked);
+    }
+    if needs_approval
+        && state.policy.require_approval
+        && !state.approved.contains(node)
+        && !state.message_archive.contains_key(node)
+    {
+        if state.awaiting_approval.len() < MAX_AWAITING_APPROVAL {
+            state.awaiting_approval.insert(node.to_string());
+        }
+        return Err(Rejection::AwaitingApproval);
+    }
+    if state.policy.rate_limit > 0 {
+        let now = now_secs();
+        // forget every node whose window has ended, so counts do not pile up
+        let window_secs = state.policy.rate_limit_window_secs;
+        state
+            .request_counts
+            .retain(|_, (window_start, _)| now < *window_start + window_secs);
+        let (_, count) = state
+            .request_counts
+            .entry(node.to_string())
+            .or_insert((now, 0));
+        if *count >= state.policy.rate_limit {
+            return Err(Rejection::RateLimited);
+        }
+        *count += 1;
+    }
+    Ok(())
+}
+
+/// Decide whether to serve a worker request from a peer: they may only ask us to send them a file.
+fn check_worker_request(
+    source: &Address,
+    request: &WorkerRequest,
+    state: &mut State,
+) -> Result<(), String> {
+    if let Err(rejection) = check_inbound(state, &source.node, true) {
+        return Err(format!("rejected: {rejection:?}"));
+    }
+    match request {
+        WorkerRequest::Download(DownloadRequest {
+            ref name,
+            is_requestor: false,
+            ..
+        }) if is_file_name(name) => Ok(()),
+        _ => Err(format!("rejecting {request:?} from {source}")),
+    }
+}
+
+/// File names must name a file directly within a drive: no paths, `.` or `..`.
+fn is_file_name(name: &str) -> bool {
+    !name.is_empty() && name != "." && name != ".." && !name.contains('/')
+}
+
+fn make_files_path(our: &Address, name: &str) -> String {
+    format!("{}/files/{name}", our.package_id())
+}
+
+/// Accepted files are stored under their message id so files of the same name do not collide.
+fn make_download_name(id: &str, name: &str) -> String {
+    format!("{id}-{name}")
+}
+
+/// Peers ask for files we offered them by their download name: serve the offered file.
+/// Any other name is served as-is.
+fn served_file_name<'a>(our: &Address, state: &'a State, node: &str, name: &'a str) -> &'a str {
+    state
+        .message_archive
+        .get(node)
+        .and_then(|messages| {
+            messages.iter().find_map(|m| match m.file {
+                Some(ref file)
+                    if m.author == our.node && make_download_name(&m.id, &file.name) == name =>
+                {
+                    Some(file.name.as_str())
+                }
+                _ => None,
+            })
+        })
+        .unwrap_or(name)
+}
+
+fn make_exports_path(our: &Address, name: &str) -> anyhow::Result<String> {
+    if !is_file_name(name) {
+        return Err(anyhow::anyhow!("invalid file name {name:?}"));
+    }
+    Ok(format!("{}/exports/{name}", our.package_id()))
+}
+
+fn export_json_lines(conversations: &[(&String, &Vec<ChatMessage>)]) -> anyhow::Result<String> {
+    let mut contents = String::new();
+    for (node, messages) in conversations {
+        for message in messages.iter() {
+            contents.push_str(&serde_json::to_string(&ArchiveLine {
+                node: node.to_string(),
+                message: message.clone(),
+            })?);
+            contents.push('\n');
+        }
+    }
+    Ok(contents)
+}
+
+fn export_markdown(conversations: &[(&String, &Vec<ChatMessage>)]) -> String {
+    let mut contents = String::new();
+    for (node, messages) in conversations {
+        contents.push_str(&format!("# Chat with {node}\n\n"));
+        for message in messages.iter() {
+            if message.deleted {
+                contents.push_str(&format!("**{}**: _message deleted_\n\n", message.author));
+                continue;
+            }
+            contents.push_str(&format!("**{}**: {}", message.author, message.content));
+            if let Some(ref file) = message.file {
+                contents.push_str(&format!(" [file: {} ({} bytes)]", file.name, file.size));
+            }
+            if message.edited {
+                contents.push_str(" _(edited)_");
+            }
+            contents.push_str("\n\n");
+        }
+    }
+    contents
+}
+
+fn export_archive(our: &Address, request: &ExportRequest, state: &State) -> anyhow::Result<String> {
+    let path = make_exports_path(our, &request.name)?;
+    let conversations: Vec<(&String, &Vec<ChatMessage>)> = match request.node {
+        Some(ref node) => {
+            let Some(messages) = state.message_archive.get(node) else {
+                return Err(anyhow::anyhow!("no conversation with {node}"));
+            };
+            vec![(node, messages)]
+        }
+        None => {
+            let mut conversations: Vec<_> = state.message_archive.iter().collect();
+            conversations.sort_by(|a, b| a.0.cmp(b.0));
+            conversations
+        }
+    };
+    let contents = match request.format {
+        ExportFormat::JsonLines => export_json_lines(&conversations)?,
+        ExportFormat::Markdown => export_markdown(&conversations),
+    };
+    open_file(&path, true, None)?.write(contents.as_bytes())?;
+    Ok(path)
+}
+
+/// Merge a JSON Lines export into our archive; messages whose ids we already have are skipped.
+fn import_archive(our: &Address, name: &str, state: &mut State) -> anyhow::Result<u64> {
+    let path = make_exports_path(our, name)?;
+    let contents = String::from_utf8(open_file(&path, false, None)?.read()?)?;
+
+    // parse everything before touching the archive so a bad file imports nothing
+    let mut lines = vec![];
+    for (i, line) in contents.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let line: ArchiveLine = serde_json::from_str(line)
+            .map_err(|e| anyhow::anyhow!("{path} line {}: {e}", i + 1))?;
+        lines.push(line);
+    }
+
+    let mut imported = 0;
+    for ArchiveLine { node, mut message } in lines {
+        if get_message(&state.message_archive, &node, &message.id).is_some() {
+            continue;
+        }
+        // no download survives into an import: it may be accepted again
+        if let Some(ref mut file) = message.file {
+            if let TransferStatus::Downloading(_) = file.status {
+                file.status = TransferStatus::Failed("interrupted".to_string());
+            }
+        }
+        // merge into the conversation in the order the messages were sent
+        push_message(state, &node, &message);
+        let messages = state.message_archive.entry(node).or_default();
+        let time = message_time(&message.id);
+        let index = messages.partition_point(|m| message_time(&m.id) <= time);
+        messages.insert(index, message);
+        imported += 1;
+    }
+    Ok(imported)
+}
+
+fn push_update(state: &State, update: &WsUpdate) {
+    if state.ws_channels.is_empty() {
+        return;
+    }
+    let Ok(bytes) = serde_json::to_vec(update) else {
+        return;
+    };
+    for channel_id in state.ws_channels.iter() {
+        http::server::send_ws_push(
+            *channel_id,
+            http::server::WsMessageType::Text,
+            LazyLoadBlob {
+                mime: Some("application/json".to_string()),
+                bytes: bytes.clone(),
+            },
+        );
+    }
+}
+
+fn push_message(state: &State, node: &str, message: &ChatMessage) {
+    push_update(
+        state,
+        &WsUpdate::Message {
+            node: node.to_string(),
+            message: message.clone(),
+        },
+    );
+}
+
+fn archive_message(state: &mut State, node: &str, message: ChatMessage) {
+    push_message(state, node, &message);
+    state
+        .message_archive
+        .entry(node.to_string())
+        .or_default()
+        .push(message);
+}
+
+fn get_message<'a>(
+    message_archive: &'a MessageArchive,
+    node: &str,
+    id: &str,
+) -> Option<&'a ChatMessage> {
+    message_archive.get(node)?.iter().find(|m| m.id == id)
+}
+
+fn get_message_mut<'a>(
+    message_archive: &'a mut MessageArchive,
+    node: &str,
+    id: &str,
+) -> Option<&'a mut ChatMessage> {
+    message_archive
+        .get_mut(node)?
+        .iter_mut()
+        .find(|m| m.id == id)
+}
+
+/// Hash a file by asking VFS directly so the contents never enter process memory.
+fn hash_file(path: &str) -> anyhow::Result<String> {
+    let response = Request::new()
+        .target(("our", "vfs", "distro", "sys"))
+        .body(serde_json::to_vec(&vfs::VfsRequest {
+            path: path.to_string(),
+            action: vfs::VfsAction::Hash,
+        })?)
+        .send_and_await_response(TIMEOUT_SECS)??;
+    let Ok(vfs::VfsResponse::Hash(hash)) = serde_json::from_slice(response.body()) else {
+        return Err(anyhow::anyhow!("could not hash file at {path}"));
+    };
+    Ok(hash.iter().map(|byte| format!("{byte:02x}")).collect())
+}
+
+/// Check `keys` match those pinned for `node`, pinning them if there are none.
+fn pin_peer_keys(state: &mut State, node: &str, keys: &PublicKeys) -> anyhow::Result<()> {
+    match state.peer_keys.get(node) {
+        None => {
+            state.peer_keys.insert(node.to_string(), keys.clone());
+            state.keys.save(&state.peer_keys);
+            Ok(())
+        }
+        Some(pinned) if pinned.signing == keys.signing && pinned.encryption == keys.encryption => {
+            Ok(())
+        }
+        Some(_) => Err(anyhow::anyhow!("keys of {node} do not match pinned keys")),
+    }
+}
+
+fn get_peer_keys(state: &mut State, node: &str) -> anyhow::Result<PublicKeys> {
+    if let Some(keys) = state.peer_keys.get(node) {
+        return Ok(keys.clone());
+    }
+    let response = Request::new()
+        .target(make_chat_address(node))
+        .body(ChatRequest::PublicKeys)
+        .send_and_await_response(TIMEOUT_SECS)??;
+    let keys = match response.body().try_into() {
+        Ok(ChatResponse::PublicKeys(keys)) => keys,
+        Ok(ChatResponse::Rejected(rejection)) => {
+            return Err(anyhow::anyhow!("{node} rejected request: {rejection:?}"))
+        }
+        _ => return Err(anyhow::anyhow!("{node} did not send public keys")),
+    };
+    pin_peer_keys(state, node, &keys)?;
+    Ok(keys)
+}
+
+/// Open a payload sealed by `node`, refusing it if signed by other than their pinned keys.
+fn open_sealed<T: serde::de::DeserializeOwned>(
+    our: &Address,
+    node: &str,
+    sealed: &Sealed,
+    state: &mut State,
+) -> anyhow::Result<T> {
+    if let Some(pinned) = state.peer_keys.get(node) {
+        if pinned.signing != sealed.sender_keys.signing
+            || pinned.encryption != sealed.sender_keys.encryption
+        {
+            return Err(anyhow::anyhow!("keys of {node} do not match pinned keys"));
+        }
+    }
+    let payload = state.keys.open(node, &our.node, sealed)?;
+    pin_peer_keys(state, node, &sealed.sender_keys)?;
+    Ok(payload)
+}
+
+fn deliver_message(
+    our: &Address,
+    state: &mut State,
+    target: &str,
+    message: &ChatMessage,
+    encrypt: bool,
+) -> anyhow::Result<()> {
+    let recipient_keys = if encrypt {
+        Some(get_peer_keys(state, target)?)
+    } else {
+        None
+    };
+    let sealed = state
+        .keys
+        .seal(&our.node, target, recipient_keys.as_ref(), message)?;
+    let response = Request::new()
+        .target(make_chat_address(target))
+        .body(ChatRequest::Deliver(sealed))
+        .send_and_await_response(TIMEOUT_SECS)??;
+    match response.body().try_into() {
+        Ok(ChatResponse::Deliver) => Ok(()),
+        Ok(ChatResponse::Rejected(rejection)) => {
+            Err(anyhow::anyhow!("{target} rejected message: {rejection:?}"))
+        }
+        _ => Err(anyhow::anyhow!("{target} did not accept message")),
+    }
+}
+
+/// Changes to earlier messages are always encrypted: their keys were fetched to deliver them.
+fn send_control(
+    our: &Address,
+    state: &mut State,
+    target: &str,
+    control: &ChatControl,
+) -> anyhow::Result<()> {
+    let recipient_keys = get_peer_keys(state, target)?;
+    let sealed = state
+        .keys
+        .seal(&our.node, target, Some(&recipient_keys), control)?;
+    let response = Request::new()
+        .target(make_chat_address(target))
+        .body(ChatRequest::Control(sealed))
+        .send_and_await_response(TIMEOUT_SECS)??;
+    match response.body().try_into() {
+        Ok(ChatResponse::Control(Ok(()))) => Ok(()),
+        Ok(ChatResponse::Control(Err(e))) => Err(anyhow::anyhow!("{target} rejected change: {e}")),
+        Ok(ChatResponse::Rejected(rejection)) => {
+            Err(anyhow::anyhow!("{target} rejected change: {rejection:?}"))
+        }
+        _ => Err(anyhow::anyhow!("{target} did not accept change")),
+    }
+}
+
+fn send_presence(target: &str, presence: Presence) -> anyhow::Result<()> {
+    Request::new()
+        .target(make_chat_address(target))
+        .body(ChatRequest::Presence(presence))
+        .expects_response(TIMEOUT_SECS)
+        .send()
+}
+
+/// Tell `node` we are typing, waiting to hear they accepted it.
+fn send_typing(target: &str) -> anyhow::Result<()> {
+    let response = Request::new()
+        .target(make_chat_address(target))
+        .body(ChatRequest::Presence(Presence::Typing))
+        .send_and_await_response(TIMEOUT_SECS)??;
+    match response.body().try_into() {
+        Ok(ChatResponse::Presence) => Ok(()),
+        Ok(ChatResponse::Rejected(rejection)) => {
+            Err(anyhow::anyhow!("{target} rejected typing: {rejection:?}"))
+        }
+        _ => Err(anyhow::anyhow!("{target} did not accept typing")),
+    }
+}
+
+/// Tell `node` we have read their latest message, if we have not already.
+fn send_read_receipt(state: &mut State, node: &str) -> anyhow::Result<()> {
+    let Some(latest) = state
+        .message_archive
+        .get(node)
+        .and_then(|msgs| msgs.iter().rev().find(|m| m.author == node))
+    else {
+        return Ok(());
+    };
+    if state.read_sent.get(node) == Some(&latest.id) {
+        return Ok(());
+    }
+    let id = latest.id.clone();
+    send_presence(node, Presence::Read(id.clone()))?;
+    state.read_sent.insert(node.to_string(), id);
+    Ok(())
+}
+
+fn make_message(our: &Address, content: &str, file: Option<FileAttachment>) -> ChatMessage {
+    ChatMessage {
+        id: make_message_id(our),
+        author: our.node.clone(),
+        content: content.to_string(),
+        file,
+        edited: false,
+        deleted: false,
+        reactions: vec![],
+    }
+}
+
+/// Return `message` as changed by `action` taken by `actor`,
+/// or an error if `actor` may not take that action.
+fn apply_control(
+    message: &ChatMessage,
+    actor: &str,
+    action: &ControlAction,
+) -> anyhow::Result<ChatMessage> {
+    if message.deleted {
+        return Err(anyhow::anyhow!("message {} was deleted", message.id));
+    }
+    let mut message = message.clone();
+    match action {
+        ControlAction::Edit(ref content) => {
+            if message.author != actor {
+                return Err(anyhow::anyhow!(
+                    "{actor} cannot edit message {}",
+                    message.id
+                ));
+            }
+            message.content = content.clone();
+            message.edited = true;
+        }
+        ControlAction::Delete => {
+            if message.author != actor {
+                return Err(anyhow::anyhow!(
+                    "{actor} cannot delete message {}",
+                    message.id
+                ));
+            }
+            message.content = String::new();
+            message.file = None;
+            message.reactions = vec![];
+            message.deleted = true;
+        }
+        ControlAction::React((ref emoji, add)) => {
+            let index = message.reactions.iter().position(|r| &r.emoji == emoji);
+            match (index, *add) {
+                (Some(index), true) => {
+                    let nodes = &mut message.reactions[index].nodes;
+                    if !nodes.iter().any(|n| n == actor) {
+                        nodes.push(actor.to_string());
+                    }
+                }
+                (None, true) => message.reactions.push(Reaction {
+                    emoji: emoji.clone(),
+                    nodes: vec![actor.to_string()],
+                }),
+                (Some(index), false) => {
+                    message.reactions[index].nodes.retain(|n| n != actor);
+                    if message.reactions[index].nodes.is_empty() {
+                        message.reactions.remove(index);
+                    }
+                }
+                (None, false) => {}
+            }
+        }
+    }
+    Ok(message)
+}
+
+fn replace_message(state: &mut State, node: &str, message: ChatMessage) {
+    push_message(state, node, &message);
+    if let Some(old) = get_message_mut(&mut state.message_archive, node, &message.id) {
+        *old = message;
+    }
+}
+
+/// Change one of our messages with `node`, keeping their archive consistent with ours.
+fn change_message(
+    our: &Address,
+    node: &str,
+    id: &str,
+    action: ControlAction,
+    state: &mut State,
+) -> anyhow::Result<()> {
+    let Some(message) = get_message(&state.message_archive, node, id) else {
+        return Err(anyhow::anyhow!("no message {id} with {node}"));
+    };
+    let message = apply_control(message, &our.node, &action)?;
+    send_control(
+        our,
+        state,
+        node,
+        &ChatControl {
+            id: id.to_string(),
+            action,
+        },
+    )?;
+    replace_message(state, node, message);
+    Ok(())
+}
+
+fn make_file_message(our: &Address, name: &str, message: &str) -> anyhow::Result<ChatMessage> {
+    if !is_file_name(name) {
+        return Err(anyhow::anyhow!("invalid file name {name:?}"));
+    }
+    let path = make_files_path(our, name);
+    let size = vfs::metadata(&path, None)?.len;
+    let hash = hash_file(&path)?;
+    Ok(make_message(
+        our,
+        message,
+        Some(FileAttachment {
+            name: name.to_string(),
+            size,
+            hash,
+            status: TransferStatus::Offered,
+        }),
+    ))
+}
+
+fn accept_file(our: &Address, node: &str, id: &str, state: &mut State) -> anyhow::Result<()> {
+    let Some(message) = get_message_mut(&mut state.message_archive, node, id) else {
+        return Err(anyhow::anyhow!("no message {id} from {node}"));
+    };
+    if message.author != node {
+        return Err(anyhow::anyhow!("message {id} was not sent by {node}"));
+    }
+    let Some(ref mut file) = message.file else {
+        return Err(anyhow::anyhow!("message {id} has no file attached"));
+    };
+    if !is_file_name(&file.name) {
+        return Err(anyhow::anyhow!("invalid file name {:?}", file.name));
+    }
+    if state.downloads.keys().any(|(_, name)| name == &file.name) {
+        return Err(anyhow::anyhow!(
+            "already downloading a file named {}",
+            file.name
+        ));
+    }
+    let key = (node.to_string(), file.name.clone());
+
+    // kick off the download by sending ourselves a worker Request:
+    // `handle_worker_request()` then calls `start_download()`
+    Request::to(our)
+        .body(WorkerRequest::Download(DownloadRequest {
+            name: make_download_name(id, &file.name),
+            target: make_chat_address(node).into(),
+            is_requestor: true,
+        }))
+        .expects_response(TIMEOUT_SECS)
+        .context(serde_json::to_vec(&key)?)
+        .send()?;
+
+    file.status = TransferStatus::Downloading(0);
+    let message = message.clone();
+    state.downloads.insert(
+        key.clone(),
+        Download {
+            id: id.to_string(),
+            updates: 0,
+        },
+    );
+    watch_download(&key, 0);
+    push_message(state, node, &message);
+    Ok(())
+}
+
+/// Fail download `key` if it has not reported progress again by the time this timer goes off.
+fn watch_download(key: &DownloadKey, updates: u64) {
+    match serde_json::to_vec(&(key, updates)) {
+        Ok(context) => timer::set_timer(TRANSFER_TIMEOUT_SECS * 1000, Some(context)),
+        Err(e) => println!("failed to watch download of {}: {e:?}", key.1),
+    }
+}
+
+/// Set the status of the file of download `key`, forgetting the download once it has ended.
+fn update_download(state: &mut State, key: &DownloadKey, status: TransferStatus) {
+    let id = if let TransferStatus::Done | TransferStatus::Failed(_) = status {
+        let Some(download) = state.downloads.remove(key) else {
+            return;
+        };
+        download.id
+    } else {
+        let Some(download) = state.downloads.get_mut(key) else {
+            return;
+        };
+        download.updates += 1;
+        watch_download(key, download.updates);
+        download.id.clone()
+    };
+    let (ref node, _) = key;
+    if let Some(message) = get_message_mut(&mut state.message_archive, node, &id) {
+        if let Some(ref mut file) = message.file {
+            file.status = status;
+        }
+        let message = message.clone();
+        push_message(state, node, &message);
+    }
+}
+
+/// Requests that start downloads carry the download key as their context.
+fn download_key(context: Option<&[u8]>) -> Option<DownloadKey> {
+    serde_json::from_slice(context?).ok()
+}
+
+/// Check a finished download against the size and hash its sender offered.
+fn check_download(our: &Address, state: &State, key: &DownloadKey) -> TransferStatus {
+    let (ref node, ref name) = key;
+    let Some(download) = state.downloads.get(key) else {
+        return TransferStatus::Done;
+    };
+    let Some(FileAttachment { size, hash, .. }) =
+        get_message(&state.message_archive, node, &download.id).and_then(|m| m.file.as_ref())
+    else {
+        return TransferStatus::Done;
+    };
+    let path = make_files_path(our, &make_download_name(&download.id, name));
+    match vfs::metadata(&path, None) {
+        Ok(metadata) if metadata.len != *size => {
+            return TransferStatus::Failed(format!(
+                "size mismatch: got {} bytes, expected {size}",
+                metadata.len
+            ));
+        }
+        Ok(_) => {}
+        Err(e) => return TransferStatus::Failed(format!("could not read {path}: {e:?}")),
+    }
+    match hash_file(&path) {
+        Ok(ref h) if h == hash => TransferStatus::Done,
+        Ok(_) => TransferStatus::Failed("hash mismatch".to_string()),
+        Err(e) => TransferStatus::Failed(e.to_string()),
+    }
+}
+
+fn fail_download(state: &mut State, key: &DownloadKey, reason: String) {
+    println!("download of {} from {} failed: {reason}", key.1, key.0);
+    update_download(state, key, TransferStatus::Failed(reason));
+}
+
 fn handle_chat_request(
     our: &Address,
     source: &Address,
     request: &ChatRequest,
-    message_archive: &mut MessageArchive,
-) -> anyhow::Result<()> {
-    match request {
+    state: &mut State,
+) -> anyhow::Result<ChatResponse> {
+    // peers may only deliver messages to us, change them, or read their history with us
+    let is_peer_request = matches!(
+        request,
+        ChatRequest::Deliver(_)
+            | ChatRequest::Control(_)
+            | ChatRequest::Presence(_)
+            | ChatRequest::History(_)
+            | ChatRequest::PublicKeys
+    );
+    if source.node != our.node && !is_peer_request {
+        return Err(anyhow::anyhow!("rejecting {request:?} from {source}"));
+    }
+    if source.node != our.node {
+        // strangers may fetch our keys, so that what they then send us meets our policy instead
+        let needs_approval = !matches!(request, ChatRequest::PublicKeys);
+        if let Err(rejection) = check_inbound(state, &source.node, needs_approval) {
+            println!("rejected request from {}: {rejection:?}", source.node);
+            return Ok(ChatResponse::Rejected(rejection));
+        }
+    }
+    let response = match request {
         ChatRequest::Send(SendRequest {
             ref target,
             ref message,
+            encrypt,
         }) => {
-            if target == &our.node {
-                println!("{}: {}", source.node, message);
-                let message = ChatMessage {
-                    author: source.node.clone(),
-                    content: message.into(),
-                };
-                message_archive
-                    .entry(source.node.clone())
-                    .and_modify(|e| e.push(message.clone()))
-                    .or_insert(vec![message]);
+            // messaging a node approves it to message us back
+            state.approved.insert(target.clone());
+            let message = make_message(our, message, None);
+            let result = deliver_message(our, state, target, &message, *encrypt).map(|()| {
+                archive_message(state, target, message);
+            });
+            ChatResponse::Send(result.map_err(|e| e.to_string()))
+        }
+        ChatRequest::History(ref node) => {
+            if source.node != our.node && node != &source.node {
+                return Err(anyhow::anyhow!("rejecting History of {node} from {source}"));
+            }
+            if source.node == our.node {
+                if let Err(e) = send_read_receipt(state, node) {
+                    println!("failed to send read receipt to {node}: {e:?}");
+                }
+            }
+            ChatResponse::History(HistoryResponse {
+                messages: state
+                    .message_archive
+                    .get(node)
+                    .map(|msgs| msgs.clone())
+                    .unwrap_or_default(),
+                read_up_to: state.read_by_peer.get(node).cloned(),
+            })
+        }
+        ChatRequest::SendFile(SendFileRequest {
+            ref target,
+            ref name,
+            ref message,
+            encrypt,
+        }) => {
+            state.approved.insert(target.clone());
+            let result = make_file_message(our, name, message).and_then(|message| {
+                deliver_message(our, state, target, &message, *encrypt)?;
+                archive_message(state, target, message);
+                Ok(())
+            });
+            ChatResponse::SendFile(result.map_err(|e| e.to_string()))
+        }
+        ChatRequest::AcceptFile(AcceptFileRequest { ref node, ref id }) => {
+            let result = accept_file(our, node, id, state);
+            ChatResponse::AcceptFile(result.map_err(|e| e.to_string()))
+        }
+        ChatRequest::Edit(EditRequest {
+            ref node,
+            ref id,
+            ref content,
+        }) => {
+            let action = ControlAction::Edit(content.clone());
+            let result = change_message(our, node, id, action, state);
+            ChatResponse::Edit(result.map_err(|e| e.to_string()))
+        }
+        ChatRequest::Delete(DeleteRequest { ref node, ref id }) => {
+            let result = change_message(our, node, id, ControlAction::Delete, state);
+            ChatResponse::Delete(result.map_err(|e| e.to_string()))
+        }
+        ChatRequest::React(ReactRequest {
+            ref node,
+            ref id,
+            ref emoji,
+            add,
+        }) => {
+            let action = ControlAction::React((emoji.clone(), *add));
+            let result = change_message(our, node, id, action, state);
+            ChatResponse::React(result.map_err(|e| e.to_string()))
+        }
+        ChatRequest::Block(ref node) => {
+            state.blocked.insert(node.clone());
+            state.approved.remove(node);
+            state.awaiting_approval.remove(node);
+            ChatResponse::Block
+        }
+        ChatRequest::Unblock(ref node) => {
+            state.blocked.remove(node);
+            ChatResponse::Unblock
+        }
+        ChatRequest::Approve(ref node) => {
+            state.blocked.remove(node);
+            state.awaiting_approval.remove(node);
+            state.approved.insert(node.clone());
+            ChatResponse::Approve
+        }
+        ChatRequest::SetPolicy(ref policy) => {
+            state.policy = policy.clone();
+            state.request_counts.clear();
+            ChatResponse::SetPolicy
+        }
+        ChatRequest::GetPolicy => ChatResponse::GetPolicy(PolicyInfo {
+            policy: state.policy.clone(),
+            blocked: sorted(&state.blocked),
+            approved: sorted(&state.approved),
+            awaiting_approval: sorted(&state.awaiting_approval),
+        }),
+        ChatRequest::Typing(ref node) => {
+            ChatResponse::Typing(send_typing(node).map_err(|e| e.to_string()))
+        }
+        ChatRequest::Export(ref export_request) => ChatResponse::Export(
+            export_archive(our, export_request, state).map_err(|e| e.to_string()),
+        ),
+        ChatRequest::Import(ref name) => {
+            ChatResponse::Import(import_archive(our, name, state).map_err(|e| e.to_string()))
+        }
+        ChatRequest::PublicKeys => ChatResponse::PublicKeys(state.keys.public()),
+        ChatRequest::ForgetPeerKeys(ref node) => {
+            if state.peer_keys.remove(node).is_some() {
+                state.keys.save(&state.peer_keys);
+            }
+            ChatResponse::ForgetPeerKeys
+        }
+        ChatRequest::Deliver(ref sealed) => {
+            let mut message: ChatMessage = open_sealed(our, &source.node, sealed, state)?;
+            // trust the network for authorship, not the message body
+            message.author = source.node.clone();
+            message.edited = false;
+            message.deleted = false;
+            message.reactions = vec![];
+            // ids are chosen by the sender: hold them to its own namespace and to being new,
+            // so they cannot shadow our messages or replace earlier ones
+            if !message.id.starts_with(&format!("{}-", source.node))
+                || get_message(&state.message_archive, &source.node, &message.id).is_some()
+            {
+                return Err(anyhow::anyhow!(
+                    "rejecting message id {} from {source}",
+                    message.id
+                ));
+            }
+            if let Some(ref mut file) = message.file {
+                if !is_file_name(&file.name) {
+                    return Err(anyhow::anyhow!(
+                        "rejecting file {:?} from {source}",
+                        file.name
+                    ));
+                }
+                file.status = TransferStatus::Offered;
+                println!(
+                    "{}: {} [file: {} ({} bytes); accept with id {}]",
+                    source.node, message.content, file.name, file.size, message.id,
+                );
             } else {
-                let _ = Request::new()
-                    .target(Address {
-                        node: target.clone(),
-                        process: "chat-with-file-transfer:chat-with-file-transfer:template.os"
-                            .parse()?,
-                    })
-                    .body(request)
-                    .send_and_await_response(5)?
-                    .unwrap();
-                let message = ChatMessage {
-                    author: our.node.clone(),
-                    content: message.into(),
-                };
-                message_archive
-                    .entry(target.clone())
-                    .and_modify(|e| e.push(message.clone()))
-                    .or_insert(vec![message]);
+                println!("{}: {}", source.node, message.content);
             }
-            Response::new().body(ChatResponse::Send).send().unwrap();
+            archive_message(state, &source.node, message);
+            ChatResponse::Deliver
         }
-        ChatRequest::History(ref node) => {
-            Response::new()
-                .body(ChatResponse::History(
-                    message_archive
-                        .get(node)
-                        .map(|msgs| msgs.clone())
-                        .unwrap_or_default(),
-                ))
-                .send()
-                .unwrap();
+        ChatRequest::Control(ref sealed) => {
+            let result = open_sealed(our, &source.node, sealed, state).and_then(
+                |ChatControl { id, action }| {
+                    let Some(message) = get_message(&state.message_archive, &source.node, &id)
+                    else {
+                        return Err(anyhow::anyhow!("no message {id} with {}", source.node));
+                    };
+                    apply_control(message, &source.node, &action)
+                },
+            );
+            match result {
+                Ok(message) => {
+                    replace_message(state, &source.node, message);
+                    ChatResponse::Control(Ok(()))
+                }
+                Err(e) => ChatResponse::Control(Err(e.to_string())),
+            }
         }
-    }
-    Ok(())
+        ChatRequest::Presence(ref presence) => {
+            let node = source.node.clone();
+            match presence {
+                Presence::Typing => push_update(state, &WsUpdate::Typing { node }),
+                Presence::Read(ref id) => {
+                    // receipts can only be for our own messages to them
+                    let is_ours = get_message(&state.message_archive, &node, id)
+                        .is_some_and(|m| m.author == our.node);
+                    if !is_ours {
+                        println!("ignoring read receipt for {id} from {node}");
+                        return Ok(ChatResponse::Presence);
+                    }
+                    state.read_by_peer.insert(node.clone(), id.clone());
+                    push_update(
+                        state,
+                        &WsUpdate::Read {
+                            node,
+                            id: id.clone(),
+                        },
+                    );
+                }
+            }
+            ChatResponse::Presence
+        }
+    };
+    Ok(response)
 }
 
 fn handle_worker_request(
     our: &Address,
     source: &Address,
     request: &WorkerRequest,
+    state: &mut State,
 ) -> anyhow::Result<()> {
+    if source.node != our.node {
+        if let Err(e) = check_worker_request(source, request, state) {
+            println!("{e}");
+            if let WorkerRequest::Download(_) = request {
+                Response::new()
+                    .body(WorkerResponse::Download(Err(e)))
+                    .send()?;
+            }
+            return Ok(());
+        }
+    }
     match request {
         WorkerRequest::Download(DownloadRequest {
             ref name,
             ref target,
             is_requestor,
         }) => {
-            match start_download(
+            // peers ask for files we offered them by their download name
+            let name = if source.node == our.node {
+                name.as_str()
+            } else {
+                served_file_name(our, state, &source.node, name)
+            };
+            if let Err(e) = start_download(
                 &our.clone().into(),
                 &source.clone().into(),
                 name,
                 target,
                 *is_requestor,
             ) {
-                Ok(_) => {}
-                Err(e) => return Err(anyhow::anyhow!("{e}")),
+                // `start_download()` only responds on success
+                Response::new()
+                    .body(WorkerResponse::Download(Err(e)))
+                    .send()?;
             }
         }
         WorkerRequest::Progress(ProgressRequest { name, progress }) => {
             println!("{} progress: {}%", name, progress);
+            // there is at most one download per file name
+            let key = state.downloads.keys().find(|(_, n)| n == name).cloned();
+            if let Some(key) = key {
+                let status = if *progress >= 100 {
+                    check_download(our, state, &key)
+                } else {
+                    TransferStatus::Downloading(*progress)
+                };
+                update_download(state, &key, status);
+            }
             Response::new().body(WorkerResponse::Progress).send()?;
         }
     }
     Ok(())
 }
 
-fn handle_worker_response(response: &WorkerResponse) -> anyhow::Result<()> {
+fn handle_chat_response(source: &Address, response: &ChatResponse) -> anyhow::Result<()> {
+    if let ChatResponse::Rejected(ref rejection) = response {
+        return Err(anyhow::anyhow!(
+            "{} rejected request: {rejection:?}",
+            source.node
+        ));
+    }
+    Ok(())
+}
+
+fn handle_worker_response(
+    message: &Message,
+    response: &WorkerResponse,
+    state: &mut State,
+) -> anyhow::Result<()> {
     match response {
         WorkerResponse::Download(ref result) => {
             if let Err(e) = result {
+                if let Some(key) = download_key(message.context()) {
+                    fail_download(state, &key, e.clone());
+                }
                 return Err(anyhow::anyhow!("{e}"));
             }
         }
@@ -152,18 +1149,140 @@ fn handle_worker_response(response: &WorkerResponse) -> anyhow::Result<()> {
     Ok(())
 }
 
-fn handle_message(
+/// Fail a download whose progress timer went off before any further progress.
+fn handle_timer(message: &Message, state: &mut State) -> anyhow::Result<()> {
+    let Some(context) = message.context() else {
+        return Ok(());
+    };
+    let (key, updates): (DownloadKey, u64) = serde_json::from_slice(context)?;
+    if state
+        .downloads
+        .get(&key)
+        .is_some_and(|download| download.updates == updates)
+    {
+        let reason = format!("no progress for {TRANSFER_TIMEOUT_SECS}s");
+        fail_download(state, &key, reason);
+    }
+    Ok(())
+}
+
+fn handle_send_error(send_error: &SendError, state: &mut State) {
+    println!("got SendError: {send_error}");
+    if let Some(key) = download_key(send_error.context.as_deref()) {
+        fail_download(state, &key, send_error.to_string());
+    }
+}
+
+fn handle_http_request(
     our: &Address,
-    message: &Message,
-    message_archive: &mut MessageArchive,
-) -> anyhow::Result<()> {
+    request: &http::server::IncomingHttpRequest,
+    state: &mut State,
+) -> anyhow::Result<(http::StatusCode, Vec<u8>)> {
+    let method = request.method()?;
+    match (request.bound_path(Some(&our.process.to_string())), method) {
+        (CONVERSATIONS_PATH, http::Method::GET) => Ok((
+            http::StatusCode::OK,
+            serde_json::to_vec(&state.message_archive)?,
+        )),
+        (CHAT_PATH, http::Method::POST) => {
+            let Some(blob) = get_blob() else {
+                return Ok((http::StatusCode::BAD_REQUEST, vec![]));
+            };
+            let Ok(chat_request) = serde_json::from_slice::<ChatRequest>(blob.bytes()) else {
+                return Ok((http::StatusCode::BAD_REQUEST, vec![]));
+            };
+            if let ChatRequest::Deliver(_) | ChatRequest::Control(_) | ChatRequest::Presence(_) =
+                chat_request
+            {
+                return Ok((http::StatusCode::FORBIDDEN, vec![]));
+            }
+            // requests from our browser act on behalf of our node
+            let response = handle_chat_request(our, our, &chat_request, state)?;
+            Ok((http::StatusCode::OK, serde_json::to_vec(&response)?))
+        }
+        (FILES_PATH, http::Method::POST) => {
+            let Some(name) = request.query_params().get("name") else {
+                return Ok((http::StatusCode::BAD_REQUEST, vec![]));
+            };
+            if !is_file_name(name) {
+                return Ok((http::StatusCode::BAD_REQUEST, vec![]));
+            }
+            let Some(blob) = get_blob() else {
+                return Ok((http::StatusCode::BAD_REQUEST, vec![]));
+            };
+            open_file(&make_files_path(our, name), true, None)?.write(blob.bytes())?;
+            Ok((http::StatusCode::CREATED, vec![]))
+        }
+        _ => Ok((http::StatusCode::METHOD_NOT_ALLOWED, vec![])),
+    }
+}
+
+fn handle_http_message(our: &Address, message: &Message, state: &mut State) -> anyhow::Result<()> {
+    match http::server::HttpServerRequest::from_bytes(message.body())? {
+        http::server::HttpServerRequest::Http(ref request) => {
+            let (status, body) = match handle_http_request(our, request, state) {
+                Ok(result) => result,
+                Err(e) => (
+                    http::StatusCode::INTERNAL_SERVER_ERROR,
+                    e.to_string().into_bytes(),
+                ),
+            };
+            http::server::send_response(
+                status,
+                Some(HashMap::from([(
+                    "Content-Type".to_string(),
+                    "application/json".to_string(),
+                )])),
+                body,
+            );
+        }
+        http::server::HttpServerRequest::WebSocketOpen {
+            ref path,
+            channel_id,
+        } => {
+            if path.ends_with(WS_PATH) {
+                state.ws_channels.insert(channel_id);
+            }
+        }
+        http::server::HttpServerRequest::WebSocketClose(ref channel_id) => {
+            state.ws_channels.remove(channel_id);
+        }
+        // browsers only listen on the WebSocket: actions go through HTTP
+        http::server::HttpServerRequest::WebSocketPush { .. } => {}
+    }
+    Ok(())
+}
+
+fn handle_message(our: &Address, message: &Message, state: &mut State) -> anyhow::Result<()> {
+    if message.source().node == our.node {
+        if message.source().process == "http-server:distro:sys" {
+            return handle_http_message(our, message, state);
+        }
+        if message.source().process == "timer:distro:sys" {
+            return handle_timer(message, state);
+        }
+    }
+    #[cfg(feature = "test")]
+    if let Ok(Setup::AsOwner(ref cr)) = message.body().try_into() {
+        // the test on the first node drives the chat of every node
+        if message.source().process.process() == "chat-with-file-transfer-test" {
+            let response = handle_chat_request(our, our, cr, state)?;
+            Response::new().body(response).send()?;
+        }
+        return Ok(());
+    }
     match message.body().try_into()? {
         // requests
-        Msg::ChatRequest(ref cr) => handle_chat_request(our, message.source(), cr, message_archive),
-        Msg::WorkerRequest(ref wr) => handle_worker_request(our, message.source(), wr),
+        Msg::ChatRequest(ref cr) => {
+            let response = handle_chat_request(our, message.source(), cr, state)?;
+            Response::new().body(response).send()?;
+            Ok(())
+        }
+        Msg::WorkerRequest(ref wr) => handle_worker_request(our, message.source(), wr, state),
 
         // responses
-        Msg::WorkerResponse(ref wr) => handle_worker_response(wr),
+        Msg::ChatResponse(ref cr) => handle_chat_response(message.source(), cr),
+        Msg::WorkerResponse(ref wr) => handle_worker_response(message, wr, state),
     }
 }
 
@@ -172,7 +1291,12 @@ fn handle_message(
 #[cfg(feature = "test")]
 enum Setup {
     Caps,
-    WriteFile { name: String, contents: Vec<u8> },
+    WriteFile {
+        name: String,
+        contents: Vec<u8>,
+    },
+    /// handle a chat request as if it came from our node's owner
+    AsOwner(ChatRequest),
 }
 
 #[cfg(feature = "test")]
@@ -197,10 +1321,17 @@ fn handle_tester_setup(our: &Address, drive_path: &str) -> anyhow::Result<()> {
             };
 
             let read_cap = get_capability(&vfs_address, &vfs_read_cap).unwrap();
+            // so the test can write files for us to import
+            let vfs_write_cap = serde_json::json!({
+                "kind": "write",
+                "drive": format!("{}/exports", our.package_id()),
+            })
+            .to_string();
+            let write_cap = get_capability(&vfs_address, &vfs_write_cap).unwrap();
 
             Response::new()
                 .body(vec![])
-                .capabilities(vec![read_cap])
+                .capabilities(vec![read_cap, write_cap])
                 .send()
                 .unwrap();
             println!("sent caps");
@@ -213,6 +1344,7 @@ fn handle_tester_setup(our: &Address, drive_path: &str) -> anyhow::Result<()> {
             let file = open_file(&format!("{drive_path}/{name}"), true, None)?;
             file.write(contents)?;
         }
+        Setup::AsOwner(_) => return Err(anyhow::anyhow!("unexpected setup {message:?}")),
     }
     println!("setup done");
     Ok(())
@@ -223,15 +1355,37 @@ fn init(our: Address) {
     println!("begin");
 
     let drive_path = create_drive(our.package_id(), "files", None).unwrap();
-    let mut message_archive = HashMap::new();
+    create_drive(our.package_id(), "exports", None).unwrap();
+    let mut state = State::new();
+
+    let mut server = http::server::HttpServer::new(5);
+    server
+        .serve_file(
+            &our,
+            UI_PATH,
+            vec!["/"],
+            http::server::HttpBindingConfig::default(),
+        )
+        .unwrap();
+    for path in [CONVERSATIONS_PATH, CHAT_PATH, FILES_PATH] {
+        server
+            .bind_http_path(path, http::server::HttpBindingConfig::default())
+            .unwrap();
+    }
+    server
+        .bind_ws_path(
+            WS_PATH,
+            http::server::WsBindingConfig::new(true, false, false),
+        )
+        .unwrap();
 
     #[cfg(feature = "test")]
     handle_tester_setup(&our, &drive_path).unwrap();
 
     loop {
         match await_message() {
-            Err(send_error) => println!("got SendError: {send_error}"),
-            Ok(ref message) => match handle_message(&our, message, &mut message_archive) {
+            Err(ref send_error) => handle_send_error(send_error, &mut state),
+            Ok(ref message) => match handle_message(&our, message, &mut state) {
                 Ok(_) => {}
                 Err(e) => println!("got error while handling message: {e:?}"),
             },
@@ -0,0 +1,60 @@
+use crate::kinode::process::chat_with_file_transfer::{
+    ExportFormat, ExportRequest, Request as ChatRequest, Response as ChatResponse,
+};
+use kinode_process_lib::{await_next_message_body, call_init, println, Address, Message, Request};
+
+wit_bindgen::generate!({
+    path: "target/wit",
+    world: "chat-with-file-transfer-template-dot-os-v0",
+    generate_unused_types: true,
+    additional_derives: [serde::Deserialize, serde::Serialize],
+});
+
+call_init!(init);
+fn init(our: Address) {
+    let Ok(body) = await_next_message_body() else {
+        println!("failed to get args!");
+        return;
+    };
+
+    let args = String::from_utf8(body).unwrap_or_default();
+    let mut args = args.split_whitespace();
+
+    let Some(name) = args.next() else {
+        println!("usage:\nexport:chat-with-file-transfer:template.os file_name [node]\n\nfile names ending in .md are exported as Markdown, others as JSON Lines");
+        return;
+    };
+    let node = args.next().map(|node| node.to_string());
+    let format = if name.ends_with(".md") {
+        ExportFormat::Markdown
+    } else {
+        ExportFormat::JsonLines
+    };
+
+    let Ok(Ok(Message::Response { body, .. })) = Request::to((
+        our.node(),
+        (
+            "chat-with-file-transfer",
+            "chat-with-file-transfer",
+            "template.os",
+        ),
+    ))
+    .body(
+        serde_json::to_vec(&ChatRequest::Export(ExportRequest {
+            node,
+            name: name.into(),
+            format,
+        }))
+        .unwrap(),
+    )
+    .send_and_await_response(5) else {
+        println!("did not receive expected Response from chat-with-file-transfer:chat-with-file-transfer:template.os");
+        return;
+    };
+
+    match serde_json::from_slice(&body) {
+        Ok(ChatResponse::Export(Ok(path))) => println!("exported to {path}"),
+        Ok(ChatResponse::Export(Err(e))) => println!("export failed: {e}"),
+        _ => println!("did not receive expected Export from chat-with-file-transfer:chat-with-file-transfer:template.os"),
+    }
+}
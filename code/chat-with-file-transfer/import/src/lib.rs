@@ -0,0 +1,46 @@
+use crate::kinode::process::chat_with_file_transfer::{
+    Request as ChatRequest, Response as ChatResponse,
+};
+use kinode_process_lib::{await_next_message_body, call_init, println, Address, Message, Request};
+
+wit_bindgen::generate!({
+    path: "target/wit",
+    world: "chat-with-file-transfer-template-dot-os-v0",
+    generate_unused_types: true,
+    additional_derives: [serde::Deserialize, serde::Serialize],
+});
+
+call_init!(init);
+fn init(our: Address) {
+    let Ok(body) = await_next_message_body() else {
+        println!("failed to get args!");
+        return;
+    };
+
+    let args = String::from_utf8(body).unwrap_or_default();
+    let name = args.trim();
+    if name.is_empty() {
+        println!("usage:\nimport:chat-with-file-transfer:template.os file_name");
+        return;
+    }
+
+    let Ok(Ok(Message::Response { body, .. })) = Request::to((
+        our.node(),
+        (
+            "chat-with-file-transfer",
+            "chat-with-file-transfer",
+            "template.os",
+        ),
+    ))
+    .body(serde_json::to_vec(&ChatRequest::Import(name.into())).unwrap())
+    .send_and_await_response(5) else {
+        println!("did not receive expected Response from chat-with-file-transfer:chat-with-file-transfer:template.os");
+        return;
+    };
+
+    match serde_json::from_slice(&body) {
+        Ok(ChatResponse::Import(Ok(count))) => println!("imported {count} messages"),
+        Ok(ChatResponse::Import(Err(e))) => println!("import failed: {e}"),
+        _ => println!("did not receive expected Import from chat-with-file-transfer:chat-with-file-transfer:template.os"),
+    }
+}
@@ -30,6 +30,7 @@ fn init(our: Address) {
                 serde_json::to_vec(&ChatRequest::Send(SendRequest {
                     target: target.into(),
                     message: message.into(),
+                    encrypt: true,
                 }))
                 .unwrap(),
             )
@@ -39,8 +40,9 @@ fn init(our: Address) {
         return;
     };
 
-    let Ok(ChatResponse::Send) = serde_json::from_slice(&body) else {
-        println!("did not receive expected Ack from chat_with_file_transfer:chat_with_file_transfer:template.os");
-        return;
-    };
+    match serde_json::from_slice(&body) {
+        Ok(ChatResponse::Send(Ok(()))) => {}
+        Ok(ChatResponse::Send(Err(e))) => println!("send failed: {e}"),
+        _ => println!("did not receive expected Ack from chat_with_file_transfer:chat_with_file_transfer:template.os"),
+    }
 }
@@ -1,15 +1,20 @@
 use crate::kinode::process::chat_with_file_transfer::{
-    ChatMessage, Request as ChatRequest, Response as ChatResponse, SendRequest,
+    AcceptFileRequest, ChatMessage, EditRequest, ExportFormat, ExportRequest, FileAttachment,
+    HistoryResponse, Presence, Request as ChatRequest, Response as ChatResponse, SendFileRequest,
+    SendRequest, TransferStatus,
+};
+use crate::kinode::process::file_transfer_worker::{
+    DownloadRequest, Request as WorkerRequest, Response as WorkerResponse,
 };
-use crate::kinode::process::file_transfer_worker::{DownloadRequest, Request as WorkerRequest};
 use crate::kinode::process::standard::{Address as WitAddress, ProcessId as WitProcessId};
 use crate::kinode::process::tester::{
     FailResponse, Request as TesterRequest, Response as TesterResponse, RunRequest,
 };
 
 use kinode_process_lib::{
-    await_message, call_init, our_capabilities, println, save_capabilities, vfs::File, Address,
-    ProcessId, Request, Response,
+    await_message, call_init, our_capabilities, println, save_capabilities,
+    vfs::{open_file, File},
+    Address, ProcessId, Request, Response,
 };
 
 mod tester_lib;
@@ -24,6 +29,8 @@ wit_bindgen::generate!({
 const FILE_NAME: &str = "my_file.txt";
 const FILE_CONTENTS: &str = "hi";
 const DRIVE_PATH: &str = "chat-with-file-transfer:template.os";
+/// requests their chat accepts from one node per window by default
+const RATE_LIMIT: u32 = 30;
 
 impl From<Address> for WitAddress {
     fn from(address: Address) -> Self {
@@ -44,10 +51,18 @@ impl From<ProcessId> for WitProcessId {
     }
 }
 
+/// One line of a JSON Lines export
+#[derive(Debug, serde::Deserialize, serde::Serialize)]
+struct ArchiveLine {
+    node: String,
+    message: ChatMessage,
+}
+
 #[derive(Debug, serde::Deserialize, serde::Serialize, process_macros::SerdeJsonInto)]
 enum Setup {
     Caps,
     WriteFile { name: String, contents: Vec<u8> },
+    AsOwner(ChatRequest),
 }
 
 fn make_chat_address(node: &str) -> Address {
@@ -61,6 +76,61 @@ fn make_chat_address(node: &str) -> Address {
     }
 }
 
+fn get_history_response(chat_address: &Address, node: &str) -> anyhow::Result<HistoryResponse> {
+    let response = Request::new()
+        .target(chat_address.clone())
+        .body(ChatRequest::History(node.to_string()))
+        .send_and_await_response(15)??;
+    let ChatResponse::History(history) = response.body().try_into()? else {
+        return Err(anyhow::anyhow!("unexpected Response to History"));
+    };
+    Ok(history)
+}
+
+fn get_history(chat_address: &Address, node: &str) -> anyhow::Result<Vec<ChatMessage>> {
+    Ok(get_history_response(chat_address, node)?.messages)
+}
+
+/// Have `chat_address` handle `request` as if from its node's owner.
+fn as_owner(chat_address: &Address, request: ChatRequest) -> anyhow::Result<ChatResponse> {
+    let response = Request::new()
+        .target(chat_address.clone())
+        .body(Setup::AsOwner(request))
+        .send_and_await_response(15)??;
+    Ok(response.body().try_into()?)
+}
+
+fn export(
+    chat_address: &Address,
+    node: Option<String>,
+    name: &str,
+    format: ExportFormat,
+) -> anyhow::Result<Result<String, String>> {
+    let response = Request::new()
+        .target(chat_address.clone())
+        .body(ChatRequest::Export(ExportRequest {
+            node,
+            name: name.to_string(),
+            format,
+        }))
+        .send_and_await_response(15)??;
+    let ChatResponse::Export(result) = response.body().try_into()? else {
+        return Err(anyhow::anyhow!("unexpected Response to Export"));
+    };
+    Ok(result)
+}
+
+fn import(chat_address: &Address, name: &str) -> anyhow::Result<Result<u64, String>> {
+    let response = Request::new()
+        .target(chat_address.clone())
+        .body(ChatRequest::Import(name.to_string()))
+        .send_and_await_response(15)??;
+    let ChatResponse::Import(result) = response.body().try_into()? else {
+        return Err(anyhow::anyhow!("unexpected Response to Import"));
+    };
+    Ok(result)
+}
+
 fn setup(our: &Address, their: &str) -> anyhow::Result<()> {
     let our_chat_address = make_chat_address(&our.node);
     let their_chat_address = make_chat_address(their);
@@ -128,26 +198,35 @@ fn handle_message(our: &Address) -> anyhow::Result<()> {
         .body(ChatRequest::Send(SendRequest {
             target: node_names[1].clone(),
             message: message.clone(),
+            encrypt: true,
         }))
         .send_and_await_response(15)?
         .unwrap();
 
     // Get history from receiver & test
     println!("chat-with-file-transfer-test: c");
-    let response = Request::new()
-        .target(their_chat_address.clone())
-        .body(ChatRequest::History(our.node.clone()))
-        .send_and_await_response(15)?
-        .unwrap();
-    if response.is_request() {
+    let Ok(messages) = get_history(&their_chat_address, &our.node) else {
         fail!("chat-with-file-transfer-test");
     };
-    let ChatResponse::History(messages) = response.body().try_into()? else {
+    let Some(id) = messages.first().map(|m| m.id.clone()) else {
         fail!("chat-with-file-transfer-test");
     };
+    // ids are assigned by the sender, so compare everything else
+    let messages: Vec<ChatMessage> = messages
+        .into_iter()
+        .map(|m| ChatMessage {
+            id: String::new(),
+            ..m
+        })
+        .collect();
     let expected_messages = vec![ChatMessage {
+        id: String::new(),
         author: our.node.clone(),
         content: message,
+        file: None,
+        edited: false,
+        deleted: false,
+        reactions: vec![],
     }];
 
     if messages != expected_messages {
@@ -155,24 +234,92 @@ fn handle_message(our: &Address) -> anyhow::Result<()> {
         fail!("chat-with-file-transfer-test");
     }
 
-    // Test file_transfer_worker
-    println!("chat-with-file-transfer-test: d");
+    // Edit & check receiver sees the edit
+    println!("chat-with-file-transfer-test: c (edit)");
+    let edited_message: String = "hello, edited".into();
     let response = Request::new()
         .target(our_chat_address.clone())
-        .body(WorkerRequest::Download(DownloadRequest {
+        .body(ChatRequest::Edit(EditRequest {
+            node: node_names[1].clone(),
+            id: id.clone(),
+            content: edited_message.clone(),
+        }))
+        .send_and_await_response(15)?
+        .unwrap();
+    let Ok(ChatResponse::Edit(Ok(()))) = response.body().try_into() else {
+        fail!("chat-with-file-transfer-test");
+    };
+    let Ok(messages) = get_history(&their_chat_address, &our.node) else {
+        fail!("chat-with-file-transfer-test");
+    };
+    if !messages
+        .iter()
+        .any(|m| m.id == id && m.edited && m.content == edited_message)
+    {
+        println!("{messages:?} missing edit");
+        fail!("chat-with-file-transfer-test");
+    }
+
+    // Send a file from their node & accept it: it is downloaded, checked and marked Done
+    println!("chat-with-file-transfer-test: d");
+    let Ok(ChatResponse::SendFile(Ok(()))) = as_owner(
+        &their_chat_address,
+        ChatRequest::SendFile(SendFileRequest {
+            target: our.node.clone(),
             name: FILE_NAME.to_string(),
-            target: their_chat_address.into(),
-            is_requestor: true,
+            message: String::new(),
+            encrypt: false,
+        }),
+    ) else {
+        fail!("chat-with-file-transfer-test");
+    };
+    let Ok(messages) = get_history(&our_chat_address, &node_names[1]) else {
+        fail!("chat-with-file-transfer-test");
+    };
+    let Some(file_id) = messages
+        .iter()
+        .find(|m| {
+            m.file
+                .as_ref()
+                .is_some_and(|f| f.name == FILE_NAME && f.status == TransferStatus::Offered)
+        })
+        .map(|m| m.id.clone())
+    else {
+        fail!("chat-with-file-transfer-test");
+    };
+    let response = Request::new()
+        .target(our_chat_address.clone())
+        .body(ChatRequest::AcceptFile(AcceptFileRequest {
+            node: node_names[1].clone(),
+            id: file_id.clone(),
         }))
         .send_and_await_response(15)?
         .unwrap();
-    if response.is_request() {
+    let Ok(ChatResponse::AcceptFile(Ok(()))) = response.body().try_into() else {
         fail!("chat-with-file-transfer-test");
     };
-    std::thread::sleep(std::time::Duration::from_secs(3));
+    let mut status = None;
+    for _ in 0..10 {
+        std::thread::sleep(std::time::Duration::from_secs(1));
+        let Ok(messages) = get_history(&our_chat_address, &node_names[1]) else {
+            fail!("chat-with-file-transfer-test");
+        };
+        status = messages
+            .into_iter()
+            .find(|m| m.id == file_id)
+            .and_then(|m| m.file)
+            .map(|f| f.status);
+        if let Some(TransferStatus::Done | TransferStatus::Failed(_)) = status {
+            break;
+        }
+    }
+    if status != Some(TransferStatus::Done) {
+        println!("{status:?} != Done");
+        fail!("chat-with-file-transfer-test");
+    }
 
     let file = File {
-        path: format!("{DRIVE_PATH}/files/{FILE_NAME}"),
+        path: format!("{DRIVE_PATH}/files/{file_id}-{FILE_NAME}"),
         timeout: 5,
     };
     let file_contents = file.read()?;
@@ -180,6 +327,235 @@ fn handle_message(our: &Address) -> anyhow::Result<()> {
         fail!("chat-with-file-transfer-test");
     }
 
+    // Typing is accepted by them but never archived; once they block us it is refused
+    println!("chat-with-file-transfer-test: d (typing)");
+    let Ok(before) = get_history(&their_chat_address, &our.node) else {
+        fail!("chat-with-file-transfer-test");
+    };
+    let response = Request::new()
+        .target(our_chat_address.clone())
+        .body(ChatRequest::Typing(node_names[1].clone()))
+        .send_and_await_response(15)?
+        .unwrap();
+    let Ok(ChatResponse::Typing(Ok(()))) = response.body().try_into() else {
+        fail!("chat-with-file-transfer-test");
+    };
+    let Ok(after) = get_history(&their_chat_address, &our.node) else {
+        fail!("chat-with-file-transfer-test");
+    };
+    if before != after {
+        println!("{before:?} != {after:?}");
+        fail!("chat-with-file-transfer-test");
+    }
+    let Ok(ChatResponse::Block) =
+        as_owner(&their_chat_address, ChatRequest::Block(our.node.clone()))
+    else {
+        fail!("chat-with-file-transfer-test");
+    };
+    let response = Request::new()
+        .target(our_chat_address.clone())
+        .body(ChatRequest::Typing(node_names[1].clone()))
+        .send_and_await_response(15)?
+        .unwrap();
+    let Ok(ChatResponse::Typing(Err(_))) = response.body().try_into() else {
+        fail!("chat-with-file-transfer-test");
+    };
+    let Ok(ChatResponse::Unblock) =
+        as_owner(&their_chat_address, ChatRequest::Unblock(our.node.clone()))
+    else {
+        fail!("chat-with-file-transfer-test");
+    };
+
+    // Reading their message in our history sends them a receipt, which they record;
+    // receipts for messages other than their own are ignored
+    println!("chat-with-file-transfer-test: d (read receipt)");
+    let Ok(ChatResponse::Send(Ok(()))) = as_owner(
+        &their_chat_address,
+        ChatRequest::Send(SendRequest {
+            target: our.node.clone(),
+            message: "hello back".into(),
+            encrypt: true,
+        }),
+    ) else {
+        fail!("chat-with-file-transfer-test");
+    };
+    let Ok(messages) = get_history(&our_chat_address, &node_names[1]) else {
+        fail!("chat-with-file-transfer-test");
+    };
+    let Some(reply_id) = messages
+        .iter()
+        .rev()
+        .find(|m| m.author == node_names[1])
+        .map(|m| m.id.clone())
+    else {
+        fail!("chat-with-file-transfer-test");
+    };
+    let mut read_up_to = None;
+    for _ in 0..5 {
+        std::thread::sleep(std::time::Duration::from_millis(500));
+        let Ok(history) = get_history_response(&their_chat_address, &our.node) else {
+            fail!("chat-with-file-transfer-test");
+        };
+        read_up_to = history.read_up_to;
+        if read_up_to.as_ref() == Some(&reply_id) {
+            break;
+        }
+    }
+    if read_up_to.as_ref() != Some(&reply_id) {
+        println!("{read_up_to:?} != {reply_id:?}");
+        fail!("chat-with-file-transfer-test");
+    }
+    let response = Request::new()
+        .target(their_chat_address.clone())
+        .body(ChatRequest::Presence(Presence::Read(id.clone())))
+        .send_and_await_response(15)?
+        .unwrap();
+    let Ok(ChatResponse::Presence) = response.body().try_into() else {
+        fail!("chat-with-file-transfer-test");
+    };
+    let Ok(history) = get_history_response(&their_chat_address, &our.node) else {
+        fail!("chat-with-file-transfer-test");
+    };
+    if history.read_up_to.as_ref() != Some(&reply_id) {
+        println!("{:?} != {reply_id:?}", history.read_up_to);
+        fail!("chat-with-file-transfer-test");
+    }
+
+    // Export & import: re-importing our own export adds nothing; Markdown cannot be imported
+    println!("chat-with-file-transfer-test: d (export)");
+    let their = Some(node_names[1].clone());
+    let Ok(Ok(path)) = export(
+        &our_chat_address,
+        their.clone(),
+        "backup.jsonl",
+        ExportFormat::JsonLines,
+    ) else {
+        fail!("chat-with-file-transfer-test");
+    };
+    if path != format!("{DRIVE_PATH}/exports/backup.jsonl") {
+        println!("unexpected export path {path}");
+        fail!("chat-with-file-transfer-test");
+    }
+    let Ok(Ok(0)) = import(&our_chat_address, "backup.jsonl") else {
+        fail!("chat-with-file-transfer-test");
+    };
+    let Ok(Ok(_)) = export(
+        &our_chat_address,
+        their,
+        "backup.md",
+        ExportFormat::Markdown,
+    ) else {
+        fail!("chat-with-file-transfer-test");
+    };
+    let Ok(Err(_)) = import(&our_chat_address, "backup.md") else {
+        fail!("chat-with-file-transfer-test");
+    };
+    let Ok(Err(_)) = export(
+        &our_chat_address,
+        Some("nobody.dev".into()),
+        "none.jsonl",
+        ExportFormat::JsonLines,
+    ) else {
+        fail!("chat-with-file-transfer-test");
+    };
+    let Ok(Err(_)) = export(
+        &our_chat_address,
+        None,
+        "../escape.jsonl",
+        ExportFormat::JsonLines,
+    ) else {
+        fail!("chat-with-file-transfer-test");
+    };
+
+    // Import into a history missing messages: they are merged in the order they were sent,
+    // and a download that was in progress when exported is marked failed
+    println!("chat-with-file-transfer-test: d (import)");
+    let Ok(before) = get_history(&our_chat_address, &node_names[1]) else {
+        fail!("chat-with-file-transfer-test");
+    };
+    let Some(existing) = before.first().cloned() else {
+        fail!("chat-with-file-transfer-test");
+    };
+    let older = ChatMessage {
+        id: format!("{}-1", node_names[1]),
+        author: node_names[1].clone(),
+        content: "older".into(),
+        file: None,
+        edited: false,
+        deleted: false,
+        reactions: vec![],
+    };
+    let newer = ChatMessage {
+        id: format!("{}-{}", node_names[1], u128::MAX),
+        content: "newer".into(),
+        file: Some(FileAttachment {
+            name: FILE_NAME.to_string(),
+            size: FILE_CONTENTS.len() as u64,
+            hash: String::new(),
+            status: TransferStatus::Downloading(50),
+        }),
+        ..older.clone()
+    };
+    let mut contents = String::new();
+    for message in [newer.clone(), existing, older.clone()] {
+        contents.push_str(&serde_json::to_string(&ArchiveLine {
+            node: node_names[1].clone(),
+            message,
+        })?);
+        contents.push('\n');
+    }
+    open_file(
+        &format!("{DRIVE_PATH}/exports/missing.jsonl"),
+        true,
+        Some(5),
+    )?
+    .write(contents.as_bytes())?;
+    let Ok(Ok(2)) = import(&our_chat_address, "missing.jsonl") else {
+        fail!("chat-with-file-transfer-test");
+    };
+    let Ok(after) = get_history(&our_chat_address, &node_names[1]) else {
+        fail!("chat-with-file-transfer-test");
+    };
+    let mut expected = vec![older];
+    expected.extend(before);
+    expected.push(ChatMessage {
+        file: Some(FileAttachment {
+            status: TransferStatus::Failed("interrupted".into()),
+            ..newer.file.clone().unwrap()
+        }),
+        ..newer
+    });
+    if after != expected {
+        println!("{after:?} != {expected:?}");
+        fail!("chat-with-file-transfer-test");
+    }
+
+    // Peers may only ask to be sent files, and are held to the inbound policy;
+    // keep last: it leaves us rate limited by them
+    println!("chat-with-file-transfer-test: e");
+    let mut rate_limited = false;
+    for _ in 0..=RATE_LIMIT {
+        let response = Request::new()
+            .target(their_chat_address.clone())
+            .body(WorkerRequest::Download(DownloadRequest {
+                name: FILE_NAME.to_string(),
+                target: our_chat_address.clone().into(),
+                is_requestor: true,
+            }))
+            .send_and_await_response(15)??;
+        match response.body().try_into() {
+            Ok(WorkerResponse::Download(Err(ref e))) if e.contains("RateLimited") => {
+                rate_limited = true;
+                break;
+            }
+            Ok(WorkerResponse::Download(Err(_))) => {}
+            _ => fail!("chat-with-file-transfer-test"),
+        }
+    }
+    if !rate_limited {
+        fail!("chat-with-file-transfer-test");
+    }
+
     Response::new()
         .body(TesterResponse::Run(Ok(())))
         .send()
@@ -1,6 +1,9 @@
 use clap::{Parser, Subcommand};
 
-use crate::kinode::process::server::{get_file, list_files, put_file};
+use crate::kinode::process::server::{
+    delete_file, download_file, get_file, list_files, put_file, rename_file, restore, set_quotas,
+    usage, Quotas,
+};
 use kinode_process_lib::{await_next_message_body, call_init, get_blob, println, Address};
 
 wit_bindgen::generate!({
@@ -32,9 +35,56 @@ enum Command {
         host: String,
         #[arg(short, long)]
         name: String,
+        /// Write the file to this local VFS path, which allows files of any size.
+        #[arg(short, long)]
+        path: Option<String>,
+        /// Retrieve this earlier version rather than the current one.
+        #[arg(short, long)]
+        version: Option<u64>,
     },
     /// List all files we have stored on remote `host`.
-    ListFiles { host: String },
+    ListFiles {
+        host: String,
+        /// Also list earlier versions of each file.
+        #[arg(long)]
+        versions: bool,
+    },
+    /// Remove a file we have stored on remote `host`.
+    DeleteFile {
+        host: String,
+        #[arg(short, long)]
+        name: String,
+    },
+    /// Rename a file we have stored on remote `host`.
+    RenameFile {
+        host: String,
+        #[arg(short, long)]
+        from: String,
+        #[arg(short, long)]
+        to: String,
+    },
+    /// Make an earlier version of a file we have stored on remote `host` current again.
+    Restore {
+        host: String,
+        #[arg(short, long)]
+        name: String,
+        #[arg(short, long)]
+        version: u64,
+    },
+    /// Report what we store on remote `host` against its quotas.
+    Usage { host: String },
+    /// Set the quotas of `host`, which must be our own node; omitted quotas are unlimited.
+    SetQuotas {
+        host: String,
+        #[arg(long)]
+        client_bytes: Option<u64>,
+        #[arg(long)]
+        client_files: Option<u64>,
+        #[arg(long)]
+        total_bytes: Option<u64>,
+        #[arg(long)]
+        file_versions: Option<u64>,
+    },
 }
 
 fn handle_put_file(host: &str, path: &str, name: &str) -> anyhow::Result<()> {
@@ -47,8 +97,8 @@ fn handle_put_file(host: &str, path: &str, name: &str) -> anyhow::Result<()> {
     }
 }
 
-fn handle_get_file(host: &str, name: &str) -> anyhow::Result<()> {
-    match get_file(host, name) {
+fn handle_get_file(host: &str, name: &str, version: Option<u64>) -> anyhow::Result<()> {
+    match get_file(host, name, version) {
         Err(e) => Err(anyhow::anyhow!("{e}")),
         Ok(_) => {
             if let Some(blob) = get_blob() {
@@ -63,11 +113,76 @@ fn handle_get_file(host: &str, name: &str) -> anyhow::Result<()> {
     }
 }
 
-fn handle_list_files(host: &str) -> anyhow::Result<()> {
-    match list_files(host) {
+fn handle_download_file(
+    host: &str,
+    name: &str,
+    version: Option<u64>,
+    path: &str,
+) -> anyhow::Result<()> {
+    match download_file(host, name, version, path) {
+        Err(e) => Err(anyhow::anyhow!("{e}")),
+        Ok(_) => {
+            println!("Successfully GetFile {name} from host {host} to {path}.");
+            Ok(())
+        }
+    }
+}
+
+fn handle_list_files(host: &str, versions: bool) -> anyhow::Result<()> {
+    match list_files(host, versions) {
         Err(e) => Err(anyhow::anyhow!("{e}")),
-        Ok(paths) => {
-            println!("{paths:#?}");
+        Ok(files) => {
+            println!("{files:#?}");
+            Ok(())
+        }
+    }
+}
+
+fn handle_delete_file(host: &str, name: &str) -> anyhow::Result<()> {
+    match delete_file(host, name) {
+        Err(e) => Err(anyhow::anyhow!("{e}")),
+        Ok(_) => {
+            println!("Successfully DeleteFile {name} from host {host}.");
+            Ok(())
+        }
+    }
+}
+
+fn handle_rename_file(host: &str, from: &str, to: &str) -> anyhow::Result<()> {
+    match rename_file(host, from, to) {
+        Err(e) => Err(anyhow::anyhow!("{e}")),
+        Ok(_) => {
+            println!("Successfully RenameFile {from} to {to} on host {host}.");
+            Ok(())
+        }
+    }
+}
+
+fn handle_restore(host: &str, name: &str, version: u64) -> anyhow::Result<()> {
+    match restore(host, name, version) {
+        Err(e) => Err(anyhow::anyhow!("{e}")),
+        Ok(_) => {
+            println!("Successfully Restore version {version} of {name} on host {host}.");
+            Ok(())
+        }
+    }
+}
+
+fn handle_usage(host: &str) -> anyhow::Result<()> {
+    match usage(host) {
+        Err(e) => Err(anyhow::anyhow!("{e}")),
+        Ok(usage) => {
+            println!("{usage:#?}");
+            Ok(())
+        }
+    }
+}
+
+fn handle_set_quotas(host: &str, quotas: &Quotas) -> anyhow::Result<()> {
+    match set_quotas(host, quotas) {
+        Err(e) => Err(anyhow::anyhow!("{e}")),
+        Ok(_) => {
+            println!("Successfully SetQuotas on host {host}.");
             Ok(())
         }
     }
@@ -87,8 +202,46 @@ fn execute() -> anyhow::Result<()> {
             path,
             &name.unwrap_or_else(|| path.split('/').last().unwrap().to_string()),
         )?,
-        Some(Command::GetFile { ref host, ref name }) => handle_get_file(host, name)?,
-        Some(Command::ListFiles { ref host }) => handle_list_files(host)?,
+        Some(Command::GetFile {
+            ref host,
+            ref name,
+            path: None,
+            version,
+        }) => handle_get_file(host, name, version)?,
+        Some(Command::GetFile {
+            ref host,
+            ref name,
+            path: Some(ref path),
+            version,
+        }) => handle_download_file(host, name, version, path)?,
+        Some(Command::ListFiles { ref host, versions }) => handle_list_files(host, versions)?,
+        Some(Command::DeleteFile { ref host, ref name }) => handle_delete_file(host, name)?,
+        Some(Command::RenameFile {
+            ref host,
+            ref from,
+            ref to,
+        }) => handle_rename_file(host, from, to)?,
+        Some(Command::Restore {
+            ref host,
+            ref name,
+            version,
+        }) => handle_restore(host, name, version)?,
+        Some(Command::Usage { ref host }) => handle_usage(host)?,
+        Some(Command::SetQuotas {
+            ref host,
+            client_bytes,
+            client_files,
+            total_bytes,
+            file_versions,
+        }) => handle_set_quotas(
+            host,
+            &Quotas {
+                client_bytes,
+                client_files,
+                total_bytes,
+                file_versions,
+            },
+        )?,
         None => {}
     }
     Ok(())
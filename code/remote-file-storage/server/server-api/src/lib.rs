@@ -1,5 +1,8 @@
-use crate::exports::kinode::process::server::{ClientRequest, ClientResponse, Guest};
-use kinode_process_lib::{vfs, Request, Response};
+use crate::exports::kinode::process::server::{
+    BeginUploadRequest, ClientRequest, ClientResponse, FileInfo, Guest, Quotas, RangeInfo,
+    ReadRangeRequest, RestoreRequest, UploadChunkRequest, Usage,
+};
+use kinode_process_lib::{vfs, Message, Request, Response};
 
 wit_bindgen::generate!({
     path: "target/wit",
@@ -10,6 +13,22 @@ wit_bindgen::generate!({
 
 const READ_TIMEOUT_SECS: u64 = 5;
 const PUT_TIMEOUT_SECS: u64 = 5;
+/// Files larger than this are uploaded, and downloaded, in chunks of this size
+const CHUNK_BYTES: u64 = 1024 * 1024;
+
+fn send_to_server(
+    host: &str,
+    request: ClientRequest,
+    chunk: Option<Vec<u8>>,
+) -> anyhow::Result<Message> {
+    let mut request = Request::new()
+        .target((host, "server", "server", "template.os"))
+        .body(request);
+    if let Some(chunk) = chunk {
+        request = request.blob_bytes(chunk);
+    }
+    Ok(request.send_and_await_response(PUT_TIMEOUT_SECS)??)
+}
 
 fn make_put_file_error(message: &str) -> anyhow::Result<Result<(), String>> {
     Response::new()
@@ -25,14 +44,55 @@ fn make_get_file_error(message: &str) -> anyhow::Result<Result<(), String>> {
     return Err(anyhow::anyhow!(message.to_string()));
 }
 
-fn make_list_files_error(message: &str) -> anyhow::Result<Result<Vec<String>, String>> {
+fn make_list_files_error(message: &str) -> anyhow::Result<Result<Vec<FileInfo>, String>> {
     Response::new()
         .body(ClientResponse::GetFile(Err(message.to_string())))
         .send()?;
     return Err(anyhow::anyhow!(message.to_string()));
 }
 
+fn make_delete_file_error(message: &str) -> anyhow::Result<Result<(), String>> {
+    Response::new()
+        .body(ClientResponse::DeleteFile(Err(message.to_string())))
+        .send()?;
+    return Err(anyhow::anyhow!(message.to_string()));
+}
+
+fn make_rename_file_error(message: &str) -> anyhow::Result<Result<(), String>> {
+    Response::new()
+        .body(ClientResponse::RenameFile(Err(message.to_string())))
+        .send()?;
+    return Err(anyhow::anyhow!(message.to_string()));
+}
+
+fn make_usage_error(message: &str) -> anyhow::Result<Result<Usage, String>> {
+    Response::new()
+        .body(ClientResponse::Usage(Err(message.to_string())))
+        .send()?;
+    return Err(anyhow::anyhow!(message.to_string()));
+}
+
+fn make_restore_error(message: &str) -> anyhow::Result<Result<(), String>> {
+    Response::new()
+        .body(ClientResponse::Restore(Err(message.to_string())))
+        .send()?;
+    return Err(anyhow::anyhow!(message.to_string()));
+}
+
+fn make_set_quotas_error(message: &str) -> anyhow::Result<Result<(), String>> {
+    Response::new()
+        .body(ClientResponse::SetQuotas(Err(message.to_string())))
+        .send()?;
+    return Err(anyhow::anyhow!(message.to_string()));
+}
+
 fn put_file(host: String, path: String, name: String) -> anyhow::Result<Result<(), String>> {
+    // files too large to hold in memory at once are uploaded in chunks
+    let size = vfs::metadata(&path, None)?.len;
+    if size > CHUNK_BYTES {
+        return put_file_in_chunks(host, path, name, size);
+    }
+
     // rather than using `vfs::open_file()?.read()?`, which reads
     // the file into process memory, send the Request to VFS ourselves,
     // `inherit`ing the file contents into the ClientRequest
@@ -63,24 +123,160 @@ fn put_file(host: String, path: String, name: String) -> anyhow::Result<Result<(
     Ok(result)
 }
 
-fn get_file(host: String, name: String) -> anyhow::Result<Result<(), String>> {
-    let ClientResponse::GetFile(result) = Request::new()
-        .target((&host, "server", "server", "template.os"))
-        .body(ClientRequest::GetFile(name))
-        .send_and_await_response(PUT_TIMEOUT_SECS)??
+fn put_file_in_chunks(
+    host: String,
+    path: String,
+    name: String,
+    size: u64,
+) -> anyhow::Result<Result<(), String>> {
+    let ClientResponse::BeginUpload(result) = send_to_server(
+        &host,
+        ClientRequest::BeginUpload(BeginUploadRequest { name, size }),
+        None,
+    )?
+    .body()
+    .try_into()?
+    else {
+        return make_put_file_error(&format!("Got unexpected Response from server."));
+    };
+    let upload = match result {
+        Ok(upload) => upload,
+        Err(e) => return Ok(Err(e)),
+    };
+    let result = match upload_chunks(&host, &path, upload, size) {
+        Ok(Ok(())) => commit_upload(&host, upload),
+        result => result,
+    };
+    if !matches!(result, Ok(Ok(()))) {
+        // the server keeps a partial upload until it is aborted
+        let _ = send_to_server(&host, ClientRequest::AbortUpload(upload), None);
+    }
+    result
+}
+
+fn upload_chunks(
+    host: &str,
+    path: &str,
+    upload: u64,
+    size: u64,
+) -> anyhow::Result<Result<(), String>> {
+    let mut file = vfs::open_file(path, false, None)?;
+    let mut offset = 0;
+    while offset < size {
+        let mut chunk = vec![0; CHUNK_BYTES.min(size - offset) as usize];
+        file.seek(vfs::SeekFrom::Start(offset))?;
+        let read = file.read_at(&mut chunk)?;
+        if read == 0 {
+            return Err(anyhow::anyhow!("Could not read {path} at offset {offset}."));
+        }
+        chunk.truncate(read);
+        let ClientResponse::UploadChunk(result) = send_to_server(
+            host,
+            ClientRequest::UploadChunk(UploadChunkRequest { upload, offset }),
+            Some(chunk),
+        )?
         .body()
         .try_into()?
+        else {
+            return Err(anyhow::anyhow!("Got unexpected Response from server."));
+        };
+        if result.is_err() {
+            return Ok(result);
+        }
+        offset += read as u64;
+    }
+    Ok(Ok(()))
+}
+
+fn commit_upload(host: &str, upload: u64) -> anyhow::Result<Result<(), String>> {
+    let ClientResponse::CommitUpload(result) =
+        send_to_server(host, ClientRequest::CommitUpload(upload), None)?
+            .body()
+            .try_into()?
     else {
-        return make_get_file_error(&format!("Got unexpected Response from server."));
+        return Err(anyhow::anyhow!("Got unexpected Response from server."));
     };
     Ok(result)
 }
 
-fn list_files(host: String) -> anyhow::Result<Result<Vec<String>, String>> {
+fn get_file(
+    host: String,
+    name: String,
+    version: Option<u64>,
+) -> anyhow::Result<Result<(), String>> {
+    // the first range is the whole file, unless the file is too large for one range
+    let ClientResponse::ReadRange(result) = send_to_server(
+        &host,
+        ClientRequest::ReadRange(ReadRangeRequest {
+            name: name.clone(),
+            version,
+            offset: 0,
+            length: CHUNK_BYTES,
+        }),
+        None,
+    )?
+    .body()
+    .try_into()?
+    else {
+        return make_get_file_error(&format!("Got unexpected Response from server."));
+    };
+    match result {
+        Ok(RangeInfo { size, .. }) if size > CHUNK_BYTES => Ok(Err(format!(
+            "{name} is {size} bytes: retrieve it with download-file()."
+        ))),
+        Ok(_) => Ok(Ok(())),
+        Err(e) => Ok(Err(e)),
+    }
+}
+
+fn download_file(
+    host: String,
+    name: String,
+    version: Option<u64>,
+    path: String,
+) -> anyhow::Result<Result<(), String>> {
+    let mut file = vfs::create_file(&path, None)?;
+    let mut offset = 0;
+    // every range is read from the version the first one was read from,
+    // even if the file is put again while we download it
+    let mut version = version;
+    loop {
+        let message = send_to_server(
+            &host,
+            ClientRequest::ReadRange(ReadRangeRequest {
+                name: name.clone(),
+                version,
+                offset,
+                length: CHUNK_BYTES,
+            }),
+            None,
+        )?;
+        let ClientResponse::ReadRange(result) = message.body().try_into()? else {
+            return Err(anyhow::anyhow!("Got unexpected Response from server."));
+        };
+        let size = match result {
+            Ok(info) => {
+                version = Some(info.version);
+                info.size
+            }
+            Err(e) => return Ok(Err(e)),
+        };
+        if offset >= size {
+            return Ok(Ok(()));
+        }
+        let Some(range) = message.blob().filter(|range| !range.bytes().is_empty()) else {
+            return Err(anyhow::anyhow!("Got no range of {name} from server."));
+        };
+        file.append(range.bytes())?;
+        offset += range.bytes().len() as u64;
+    }
+}
+
+fn list_files(host: String, versions: bool) -> anyhow::Result<Result<Vec<FileInfo>, String>> {
     let ClientResponse::ListFiles(result) = Request::new()
         .target((&host, "server", "server", "template.os"))
         .inherit(true)
-        .body(ClientRequest::ListFiles)
+        .body(ClientRequest::ListFiles(versions))
         .send_and_await_response(PUT_TIMEOUT_SECS)??
         .body()
         .try_into()?
@@ -90,6 +286,75 @@ fn list_files(host: String) -> anyhow::Result<Result<Vec<String>, String>> {
     Ok(result)
 }
 
+fn delete_file(host: String, name: String) -> anyhow::Result<Result<(), String>> {
+    let ClientResponse::DeleteFile(result) = Request::new()
+        .target((&host, "server", "server", "template.os"))
+        .body(ClientRequest::DeleteFile(name))
+        .send_and_await_response(PUT_TIMEOUT_SECS)??
+        .body()
+        .try_into()?
+    else {
+        return make_delete_file_error(&format!("Got unexpected Response from server."));
+    };
+    Ok(result)
+}
+
+fn rename_file(
+    host: String,
+    old_name: String,
+    new_name: String,
+) -> anyhow::Result<Result<(), String>> {
+    let ClientResponse::RenameFile(result) = Request::new()
+        .target((&host, "server", "server", "template.os"))
+        .body(ClientRequest::RenameFile((old_name, new_name)))
+        .send_and_await_response(PUT_TIMEOUT_SECS)??
+        .body()
+        .try_into()?
+    else {
+        return make_rename_file_error(&format!("Got unexpected Response from server."));
+    };
+    Ok(result)
+}
+
+fn restore(host: String, name: String, version: u64) -> anyhow::Result<Result<(), String>> {
+    let ClientResponse::Restore(result) = Request::new()
+        .target((&host, "server", "server", "template.os"))
+        .body(ClientRequest::Restore(RestoreRequest { name, version }))
+        .send_and_await_response(PUT_TIMEOUT_SECS)??
+        .body()
+        .try_into()?
+    else {
+        return make_restore_error(&format!("Got unexpected Response from server."));
+    };
+    Ok(result)
+}
+
+fn usage(host: String) -> anyhow::Result<Result<Usage, String>> {
+    let ClientResponse::Usage(result) = Request::new()
+        .target((&host, "server", "server", "template.os"))
+        .body(ClientRequest::Usage)
+        .send_and_await_response(PUT_TIMEOUT_SECS)??
+        .body()
+        .try_into()?
+    else {
+        return make_usage_error(&format!("Got unexpected Response from server."));
+    };
+    Ok(result)
+}
+
+fn set_quotas(host: String, quotas: Quotas) -> anyhow::Result<Result<(), String>> {
+    let ClientResponse::SetQuotas(result) = Request::new()
+        .target((&host, "server", "server", "template.os"))
+        .body(ClientRequest::SetQuotas(quotas))
+        .send_and_await_response(PUT_TIMEOUT_SECS)??
+        .body()
+        .try_into()?
+    else {
+        return make_set_quotas_error(&format!("Got unexpected Response from server."));
+    };
+    Ok(result)
+}
+
 struct Api;
 impl Guest for Api {
     fn put_file(host: String, path: String, name: String) -> Result<(), String> {
@@ -99,18 +364,65 @@ impl Guest for Api {
         }
     }
 
-    fn get_file(host: String, name: String) -> Result<(), String> {
-        match get_file(host, name) {
+    fn get_file(host: String, name: String, version: Option<u64>) -> Result<(), String> {
+        match get_file(host, name, version) {
+            Ok(result) => result,
+            Err(e) => Err(format!("{e:?}")),
+        }
+    }
+
+    fn download_file(
+        host: String,
+        name: String,
+        version: Option<u64>,
+        path: String,
+    ) -> Result<(), String> {
+        match download_file(host, name, version, path) {
             Ok(result) => result,
             Err(e) => Err(format!("{e:?}")),
         }
     }
 
-    fn list_files(host: String) -> Result<Vec<String>, String> {
-        match list_files(host) {
+    fn list_files(host: String, versions: bool) -> Result<Vec<FileInfo>, String> {
+        match list_files(host, versions) {
             Ok(result) => result,
             Err(ref e) => Err(format!("{e:?}")),
         }
     }
+
+    fn delete_file(host: String, name: String) -> Result<(), String> {
+        match delete_file(host, name) {
+            Ok(result) => result,
+            Err(e) => Err(format!("{e:?}")),
+        }
+    }
+
+    fn rename_file(host: String, old_name: String, new_name: String) -> Result<(), String> {
+        match rename_file(host, old_name, new_name) {
+            Ok(result) => result,
+            Err(e) => Err(format!("{e:?}")),
+        }
+    }
+
+    fn restore(host: String, name: String, version: u64) -> Result<(), String> {
+        match restore(host, name, version) {
+            Ok(result) => result,
+            Err(e) => Err(format!("{e:?}")),
+        }
+    }
+
+    fn usage(host: String) -> Result<Usage, String> {
+        match usage(host) {
+            Ok(result) => result,
+            Err(e) => Err(format!("{e:?}")),
+        }
+    }
+
+    fn set_quotas(host: String, quotas: Quotas) -> Result<(), String> {
+        match set_quotas(host, quotas) {
+            Ok(result) => result,
+            Err(e) => Err(format!("{e:?}")),
+        }
+    }
 }
 export!(Api);
@@ -1,10 +1,17 @@
-use std::collections::{HashMap, HashSet};
+use std::collections::HashMap;
 
-use crate::kinode::process::server::{ClientRequest, ClientResponse};
+use crate::kinode::process::server::{
+    BeginUploadRequest, ClientRequest, ClientResponse, FileInfo, GetFileRequest, Quotas, RangeInfo,
+    ReadRangeRequest, RestoreRequest, UploadChunkRequest, Usage, VersionInfo,
+};
 use kinode_process_lib::{
-    await_message, call_init, get_blob, println, vfs, Address, Message, PackageId, Request,
-    Response,
+    await_message, call_init, get_blob, get_state, println, set_state, vfs, Address, Message,
+    PackageId, Request, Response,
 };
+use sha2::{Digest, Sha256};
+
+mod migrations;
+use migrations::Versioned;
 
 wit_bindgen::generate!({
     path: "target/wit",
@@ -13,34 +20,464 @@ wit_bindgen::generate!({
     additional_derives: [serde::Deserialize, serde::Serialize, process_macros::SerdeJsonInto],
 });
 
-type State = HashMap<String, HashSet<String>>;
+#[derive(serde::Deserialize, serde::Serialize)]
+struct State {
+    /// versions of each stored file, by client node and then file name
+    files: HashMap<String, HashMap<String, StoredFile>>,
+    quotas: Quotas,
+    /// chunked uploads in progress, by id
+    uploads: HashMap<u64, Upload>,
+    next_upload: u64,
+    /// number of references to each stored blob, by hash of its contents
+    blobs: HashMap<String, u64>,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        Self {
+            files: HashMap::new(),
+            quotas: default_quotas(),
+            uploads: HashMap::new(),
+            next_upload: 0,
+            blobs: HashMap::new(),
+        }
+    }
+}
+
+#[derive(serde::Deserialize, serde::Serialize)]
+struct StoredFile {
+    current: StoredVersion,
+    /// oldest first
+    earlier: Vec<StoredVersion>,
+}
+
+#[derive(Clone, serde::Deserialize, serde::Serialize)]
+struct StoredVersion {
+    version: u64,
+    size: u64,
+    /// hash of the contents, under which they are stored in the blobs drive
+    hash: String,
+}
+
+impl StoredFile {
+    fn new(size: u64, hash: String) -> Self {
+        Self {
+            current: StoredVersion {
+                version: 0,
+                size,
+                hash,
+            },
+            earlier: vec![],
+        }
+    }
+
+    /// Size of every version together, as counted against quotas.
+    fn bytes(&self) -> u64 {
+        self.current.size + self.earlier.iter().map(|v| v.size).sum::<u64>()
+    }
+
+    fn next_version(&self) -> u64 {
+        self.earlier
+            .iter()
+            .map(|v| v.version)
+            .fold(self.current.version, u64::max)
+            + 1
+    }
+
+    /// Bytes dropped when the current version becomes an earlier one,
+    /// keeping at most `kept` earlier versions.
+    fn dropped_bytes(&self, kept: Option<u64>) -> u64 {
+        let sizes: Vec<u64> = self
+            .earlier
+            .iter()
+            .chain([&self.current])
+            .map(|v| v.size)
+            .collect();
+        let dropped = sizes.len().saturating_sub(kept_versions(kept));
+        sizes[..dropped].iter().sum()
+    }
+
+    fn info(&self, name: &str, versions: bool) -> FileInfo {
+        FileInfo {
+            name: name.to_string(),
+            version: self.current.version,
+            size: self.current.size,
+            earlier: if versions {
+                self.earlier
+                    .iter()
+                    .map(|v| VersionInfo {
+                        version: v.version,
+                        size: v.size,
+                    })
+                    .collect()
+            } else {
+                vec![]
+            },
+        }
+    }
+}
+
+#[derive(serde::Deserialize, serde::Serialize)]
+struct Upload {
+    source: String,
+    name: String,
+    size: u64,
+    received: u64,
+    /// when the upload last began or received a chunk, in seconds since the epoch
+    updated: u64,
+}
+
 const READ_TIMEOUT_SECS: u64 = 5;
+const DEFAULT_CLIENT_BYTES: u64 = 100 * 1024 * 1024;
+const DEFAULT_CLIENT_FILES: u64 = 1_000;
+const DEFAULT_TOTAL_BYTES: u64 = 1024 * 1024 * 1024;
+const DEFAULT_FILE_VERSIONS: u64 = 5;
+/// Uploads each client node may have in progress at once
+const MAX_CLIENT_UPLOADS: usize = 4;
+/// Uploads that receive no chunk for this long are aborted
+const UPLOAD_EXPIRY_SECS: u64 = 60 * 60;
+/// Largest chunk or range we handle at once
+const MAX_CHUNK_BYTES: u64 = 4 * 1024 * 1024;
+/// Drive that holds uploads in progress
+const UPLOADS_DRIVE: &str = "uploads";
+/// Drive that holds the contents of every version of every file by hash,
+/// so contents stored by many clients, or under many names, are stored once
+const BLOBS_DRIVE: &str = "blobs";
+
+fn default_quotas() -> Quotas {
+    Quotas {
+        client_bytes: Some(DEFAULT_CLIENT_BYTES),
+        client_files: Some(DEFAULT_CLIENT_FILES),
+        total_bytes: Some(DEFAULT_TOTAL_BYTES),
+        file_versions: Some(DEFAULT_FILE_VERSIONS),
+    }
+}
+
+fn kept_versions(kept: Option<u64>) -> usize {
+    kept.map_or(usize::MAX, |kept| {
+        usize::try_from(kept).unwrap_or(usize::MAX)
+    })
+}
+
+/// Persist the index of stored files, so it survives a restart:
+/// the files themselves already persist on their VFS drives.
+fn save_state(state: &State) {
+    set_state(&serde_json::to_vec(&Versioned::new(state)).expect("Failed to serialize state!"));
+}
+
+/// Load the saved index, upgrading it from any earlier layout. Failing to
+/// load it is fatal: starting afresh would lose track of every stored file.
+fn load_state(our: &PackageId) -> State {
+    let Some(bytes) = get_state() else {
+        return State::default();
+    };
+    let mut state: State = migrations::upgrade(&bytes)
+        .and_then(|state| {
+            serde_json::from_value(state).map_err(|e| format!("Malformed state: {e}"))
+        })
+        .unwrap_or_else(|e| panic!("failed to load state: {e}"));
+    expire_uploads(our, &mut state);
+    // save in the current layout
+    save_state(&state);
+    // only a loaded index knows which blobs nothing refers to
+    if let Err(e) = collect_garbage(our, &state) {
+        println!("failed to collect garbage: {e:?}");
+    }
+    state
+}
 
 fn make_drive_name(our: &PackageId, source: &str) -> String {
     format!("/{our}/{source}")
 }
 
-fn make_put_file_error(message: &str) -> anyhow::Result<()> {
-    Response::new()
-        .body(ClientResponse::PutFile(Err(message.to_string())))
-        .send()?;
-    return Err(anyhow::anyhow!(message.to_string()));
+fn make_upload_path(our: &PackageId, upload: u64) -> String {
+    format!("{}/{upload}", make_drive_name(our, UPLOADS_DRIVE))
 }
 
-fn make_get_file_error(message: &str) -> anyhow::Result<()> {
-    Response::new()
-        .body(ClientResponse::GetFile(Err(message.to_string())))
-        .send()?;
-    return Err(anyhow::anyhow!(message.to_string()));
+fn make_blob_path(our: &PackageId, hash: &str) -> String {
+    format!("{}/{hash}", make_drive_name(our, BLOBS_DRIVE))
+}
+
+fn hash_bytes(bytes: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(bytes))
+}
+
+/// Hash the `size` bytes of the file at `path` a range at a time,
+/// rather than reading it into process memory all at once.
+fn hash_vfs_file(path: &str, size: u64) -> anyhow::Result<String> {
+    let mut file = vfs::open_file(path, false, None)?;
+    let mut hasher = Sha256::new();
+    let mut offset = 0;
+    while offset < size {
+        // VFS fails a read past the end, rather than reading short
+        let mut range = vec![0; MAX_CHUNK_BYTES.min(size - offset) as usize];
+        file.seek(vfs::SeekFrom::Start(offset))?;
+        let read = file.read_at(&mut range)?;
+        if read == 0 {
+            return Err(anyhow::anyhow!("Could not read {path} at offset {offset}."));
+        }
+        hasher.update(&range[..read]);
+        offset += read as u64;
+    }
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Take a reference to the blob `hash`, which must already be stored.
+fn reference_blob(hash: &str, state: &mut State) {
+    *state.blobs.entry(hash.to_string()).or_default() += 1;
+}
+
+/// Drop the references of `versions` to their blobs, returning
+/// the hashes of those blobs nothing refers to any more.
+fn release_blobs(versions: Vec<StoredVersion>, state: &mut State) -> Vec<String> {
+    let mut unreferenced = vec![];
+    for StoredVersion { hash, .. } in versions {
+        let Some(references) = state.blobs.get_mut(&hash) else {
+            continue;
+        };
+        *references -= 1;
+        if *references == 0 {
+            state.blobs.remove(&hash);
+            unreferenced.push(hash);
+        }
+    }
+    unreferenced
+}
+
+/// Remove blobs nothing refers to, once the saved index no longer
+/// refers to them either: any that fail are left to `collect_garbage()`.
+fn remove_blobs(our: &PackageId, hashes: Vec<String>) {
+    for hash in hashes {
+        if let Err(e) = vfs::remove_file(&make_blob_path(our, &hash), None) {
+            println!("failed to remove blob {hash}: {e:?}");
+        }
+    }
+}
+
+fn write_blob(our: &PackageId, hash: &str, bytes: &[u8]) -> anyhow::Result<()> {
+    vfs::create_drive(our.clone(), BLOBS_DRIVE, None)?;
+    vfs::create_file(&make_blob_path(our, hash), None)?.write(bytes)?;
+    Ok(())
 }
 
-fn make_list_files_error(message: &str) -> anyhow::Result<()> {
+/// Move a complete upload into the blobs drive, unless its contents
+/// are already stored, returning the hash of its contents.
+fn store_upload(id: u64, size: u64, our: &PackageId, state: &State) -> anyhow::Result<String> {
+    let path = make_upload_path(our, id);
+    let hash = hash_vfs_file(&path, size)?;
+    if state.blobs.contains_key(&hash) {
+        // any upload left behind is removed by `collect_garbage()`
+        let _ = vfs::remove_file(&path, None);
+    } else {
+        vfs::create_drive(our.clone(), BLOBS_DRIVE, None)?;
+        if !rename_vfs_file(path, make_blob_path(our, &hash))? {
+            return Err(anyhow::anyhow!("VFS could not move it"));
+        }
+    }
+    Ok(hash)
+}
+
+fn read_vfs_range(path: &str, offset: u64, length: u64) -> anyhow::Result<Vec<u8>> {
+    let mut file = vfs::open_file(path, false, None)?;
+    file.seek(vfs::SeekFrom::Start(offset))?;
+    let mut range = vec![0; length as usize];
+    let read = file.read_at(&mut range)?;
+    range.truncate(read);
+    Ok(range)
+}
+
+/// Remove every blob and upload nothing refers to, such as one stored
+/// just before we stopped, but never recorded in the index.
+fn collect_garbage(our: &PackageId, state: &State) -> anyhow::Result<()> {
+    remove_unreferenced(our, BLOBS_DRIVE, |hash| state.blobs.contains_key(hash))?;
+    remove_unreferenced(our, UPLOADS_DRIVE, |upload| {
+        upload
+            .parse()
+            .is_ok_and(|upload| state.uploads.contains_key(&upload))
+    })
+}
+
+fn remove_unreferenced(
+    our: &PackageId,
+    drive: &str,
+    is_referenced: impl Fn(&str) -> bool,
+) -> anyhow::Result<()> {
+    let drive = vfs::create_drive(our.clone(), drive, None)?;
+    for entry in vfs::open_dir(&drive, false, None)?.read()? {
+        let name = entry.path.rsplit('/').next().unwrap_or_default();
+        if !is_referenced(name) {
+            vfs::remove_file(&entry.path, None)?;
+        }
+    }
+    Ok(())
+}
+
+/// Abort every upload that has received no chunk for `UPLOAD_EXPIRY_SECS`,
+/// so abandoned uploads do not hold on to what they reserved.
+fn expire_uploads(our: &PackageId, state: &mut State) {
+    let now = now();
+    state.uploads.retain(|upload, Upload { updated, .. }| {
+        let expired = updated.saturating_add(UPLOAD_EXPIRY_SECS) < now;
+        if expired {
+            // any upload left behind is removed by `collect_garbage()`
+            let _ = vfs::remove_file(&make_upload_path(our, *upload), None);
+        }
+        !expired
+    });
+}
+
+fn now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// Path and size of `version` of `name`, or of its current version if `None`.
+fn find_version(
+    name: &str,
+    version: Option<u64>,
+    our: &PackageId,
+    source: &str,
+    state: &State,
+) -> Option<(String, &StoredVersion)> {
+    let file = state.files.get(source)?.get(name)?;
+    let found = match version {
+        None => Some(&file.current),
+        Some(version) => file
+            .earlier
+            .iter()
+            .chain([&file.current])
+            .find(|v| v.version == version),
+    };
+    found.map(|v| (make_blob_path(our, &v.hash), v))
+}
+
+/// Keep the current version of `name`, if any, as an earlier version,
+/// dropping the oldest versions beyond the quota, so a new current version
+/// can take its place. Returns the hashes of blobs nothing refers to any more.
+fn keep_current_version(name: &str, source: &str, state: &mut State) -> Vec<String> {
+    let kept = kept_versions(state.quotas.file_versions);
+    let Some(file) = state
+        .files
+        .get_mut(source)
+        .and_then(|files| files.get_mut(name))
+    else {
+        return vec![];
+    };
+
+    file.earlier.push(file.current.clone());
+    let dropped = file.earlier.len().saturating_sub(kept);
+    let dropped = file.earlier.drain(..dropped).collect();
+    release_blobs(dropped, state)
+}
+
+/// Record `size` bytes, stored as the blob `hash`, as the newest version of `name`.
+fn record_current_version(name: &str, size: u64, hash: String, source: &str, state: &mut State) {
+    let files = state.files.entry(source.to_string()).or_default();
+    match files.get_mut(name) {
+        Some(file) => {
+            file.current = StoredVersion {
+                version: file.next_version(),
+                size,
+                hash,
+            }
+        }
+        None => {
+            files.insert(name.to_string(), StoredFile::new(size, hash));
+        }
+    }
+}
+
+/// Move a file within our VFS drives, returning whether VFS succeeded.
+fn rename_vfs_file(path: String, new_path: String) -> anyhow::Result<bool> {
+    let response = Request::new()
+        .target(("our", "vfs", "distro", "sys"))
+        .body(serde_json::to_vec(&vfs::VfsRequest {
+            path,
+            action: vfs::VfsAction::Rename { new_path },
+        })?)
+        .send_and_await_response(READ_TIMEOUT_SECS)??;
+    Ok(matches!(
+        serde_json::from_slice(response.body()),
+        Ok(vfs::VfsResponse::Ok)
+    ))
+}
+
+/// Respond with `message` as the error of the response made by `response`,
+/// and fail handling the request with it.
+fn make_error<T>(
+    response: fn(Result<T, String>) -> ClientResponse,
+    message: &str,
+) -> anyhow::Result<()> {
     Response::new()
-        .body(ClientResponse::ListFiles(Err(message.to_string())))
+        .body(response(Err(message.to_string())))
         .send()?;
     return Err(anyhow::anyhow!(message.to_string()));
 }
 
+fn make_usage(source: &str, state: &State) -> Usage {
+    let files = state.files.get(source);
+    let bytes: u64 = files.map_or(0, |files| files.values().map(StoredFile::bytes).sum());
+    let total_bytes: u64 = state
+        .files
+        .values()
+        .flat_map(|files| files.values())
+        .map(StoredFile::bytes)
+        .sum();
+    // uploads in progress reserve the size given in BeginUpload, which
+    // comes from the client, so may be large enough to overflow
+    let reserved = |upload: &&Upload| upload.source == source;
+    let uploads = state.uploads.values();
+    Usage {
+        bytes: uploads
+            .clone()
+            .filter(reserved)
+            .map(|upload| upload.size)
+            .fold(bytes, u64::saturating_add),
+        files: files.map_or(0, |files| files.len() as u64),
+        total_bytes: uploads
+            .map(|upload| upload.size)
+            .fold(total_bytes, u64::saturating_add),
+        quotas: state.quotas.clone(),
+    }
+}
+
+/// Check that storing `size` bytes as `name` keeps `source` within every quota.
+fn check_quotas(name: &str, size: u64, source: &str, state: &State) -> Result<(), String> {
+    let usage = make_usage(source, state);
+    // a file Put under an existing name becomes an earlier version,
+    // and the oldest versions beyond the quota are dropped
+    let replaced = state.files.get(source).and_then(|files| files.get(name));
+    let dropped_bytes = replaced.map_or(0, |file| file.dropped_bytes(state.quotas.file_versions));
+    // `size` comes from the client, so the sums may overflow
+    let (Some(bytes), Some(total_bytes)) = (
+        usage.bytes.saturating_sub(dropped_bytes).checked_add(size),
+        usage
+            .total_bytes
+            .saturating_sub(dropped_bytes)
+            .checked_add(size),
+    ) else {
+        return Err(format!("{size} bytes is too large to store."));
+    };
+    if let Some(limit) = state.quotas.client_bytes {
+        if bytes > limit {
+            return Err(format!("{source} would exceed its quota of {limit} bytes."));
+        }
+    }
+    if let Some(limit) = state.quotas.client_files {
+        if replaced.is_none() && usage.files + 1 > limit {
+            return Err(format!("{source} would exceed its quota of {limit} files."));
+        }
+    }
+    if let Some(limit) = state.quotas.total_bytes {
+        if total_bytes > limit {
+            return Err(format!("Host would exceed its quota of {limit} bytes."));
+        }
+    }
+    Ok(())
+}
+
 fn handle_put_file(
     name: &str,
     our: &PackageId,
@@ -48,28 +485,61 @@ fn handle_put_file(
     state: &mut State,
 ) -> anyhow::Result<()> {
     let Some(ref blob) = get_blob() else {
-        return make_put_file_error("Must give a file in the blob.");
+        return make_error(ClientResponse::PutFile, "Must give a file in the blob.");
     };
 
-    let drive = vfs::create_drive(our.clone(), source, None)?;
-    vfs::create_file(&format!("{drive}/{name}"), None)?.write(blob.bytes())?;
-    state
-        .entry(source.to_string())
-        .or_insert_with(HashSet::new)
-        .insert(name.to_string());
+    let size = blob.bytes().len() as u64;
+    if let Err(e) = check_quotas(name, size, source, state) {
+        return make_error(ClientResponse::PutFile, &e);
+    }
+
+    let hash = hash_bytes(blob.bytes());
+    if !state.blobs.contains_key(&hash) {
+        if let Err(e) = write_blob(our, &hash, blob.bytes()) {
+            return make_error(
+                ClientResponse::PutFile,
+                &format!("Could not store {name}: {e}"),
+            );
+        }
+    }
+    // reference the new contents before releasing any dropped versions,
+    // in case they are the same
+    reference_blob(&hash, state);
+    let unreferenced = keep_current_version(name, source, state);
+    record_current_version(name, size, hash, source, state);
+    save_state(state);
+    remove_blobs(our, unreferenced);
     Response::new()
         .body(ClientResponse::PutFile(Ok(())))
         .send()?;
     Ok(())
 }
 
-fn handle_get_file(name: &str, our: &PackageId, source: &str, state: &State) -> anyhow::Result<()> {
-    let Some(ref names) = state.get(source) else {
-        return make_get_file_error(&format!("{source} has no files to Get."));
+fn handle_get_file(
+    request: GetFileRequest,
+    our: &PackageId,
+    source: &str,
+    state: &State,
+) -> anyhow::Result<()> {
+    let name = request.name;
+    let Some(ref names) = state.files.get(source) else {
+        return make_error(
+            ClientResponse::GetFile,
+            &format!("{source} has no files to Get."),
+        );
     };
-    if !names.contains(name) {
-        return make_get_file_error(&format!("{source} has no such file {name}."));
+    if !names.contains_key(&name) {
+        return make_error(
+            ClientResponse::GetFile,
+            &format!("{source} has no such file {name}."),
+        );
     }
+    let Some((path, _)) = find_version(&name, request.version, our, source, state) else {
+        return make_error(
+            ClientResponse::GetFile,
+            &format!("{name} has no such version."),
+        );
+    };
 
     // rather than using `vfs::open_file()?.read()?`, which reads
     // the file into process memory, send the Request to VFS ourselves,
@@ -77,17 +547,21 @@ fn handle_get_file(name: &str, our: &PackageId, source: &str, state: &State) ->
     //
     // let contents = vfs::open_file(path, false, None)?.read()?;
     //
-    let path = format!("{}/{name}", make_drive_name(our, source));
-    let response = Request::new()
+    let Ok(Ok(response)) = Request::new()
         .target(("our", "vfs", "distro", "sys"))
         .body(serde_json::to_vec(&vfs::VfsRequest {
             path,
             action: vfs::VfsAction::Read,
         })?)
-        .send_and_await_response(READ_TIMEOUT_SECS)??;
-    let response = response.body();
-    let Ok(vfs::VfsResponse::Read) = serde_json::from_slice(&response) else {
-        return make_get_file_error(&format!("Could not find file at {name}."));
+        .send_and_await_response(READ_TIMEOUT_SECS)
+    else {
+        return make_error(ClientResponse::GetFile, &format!("Could not read {name}."));
+    };
+    let Ok(vfs::VfsResponse::Read) = serde_json::from_slice(response.body()) else {
+        return make_error(
+            ClientResponse::GetFile,
+            &format!("Could not find file at {name}."),
+        );
     };
     Response::new()
         .inherit(true)
@@ -96,14 +570,391 @@ fn handle_get_file(name: &str, our: &PackageId, source: &str, state: &State) ->
     Ok(())
 }
 
-fn handle_list_files(source: &str, state: &State) -> anyhow::Result<()> {
-    let Some(ref names) = state.get(source) else {
-        return make_list_files_error(&format!("{source} has no files to List."));
+fn handle_list_files(versions: bool, source: &str, state: &State) -> anyhow::Result<()> {
+    let Some(ref names) = state.files.get(source) else {
+        return make_error(
+            ClientResponse::ListFiles,
+            &format!("{source} has no files to List."),
+        );
+    };
+    let mut files: Vec<FileInfo> = names
+        .iter()
+        .map(|(name, file)| file.info(name, versions))
+        .collect();
+    files.sort_by(|a, b| a.name.cmp(&b.name));
+    Response::new()
+        .body(ClientResponse::ListFiles(Ok(files)))
+        .send()?;
+    Ok(())
+}
+
+fn handle_delete_file(
+    name: &str,
+    our: &PackageId,
+    source: &str,
+    state: &mut State,
+) -> anyhow::Result<()> {
+    let Some(names) = state.files.get_mut(source) else {
+        return make_error(
+            ClientResponse::DeleteFile,
+            &format!("{source} has no files to Delete."),
+        );
+    };
+    let Some(file) = names.remove(name) else {
+        return make_error(
+            ClientResponse::DeleteFile,
+            &format!("{source} has no such file {name}."),
+        );
+    };
+
+    let StoredFile {
+        current,
+        mut earlier,
+    } = file;
+    earlier.push(current);
+    let unreferenced = release_blobs(earlier, state);
+    save_state(state);
+    remove_blobs(our, unreferenced);
+    Response::new()
+        .body(ClientResponse::DeleteFile(Ok(())))
+        .send()?;
+    Ok(())
+}
+
+fn handle_rename_file(from: &str, to: &str, source: &str, state: &mut State) -> anyhow::Result<()> {
+    let Some(names) = state.files.get_mut(source) else {
+        return make_error(
+            ClientResponse::RenameFile,
+            &format!("{source} has no files to Rename."),
+        );
+    };
+    if !names.contains_key(from) {
+        return make_error(
+            ClientResponse::RenameFile,
+            &format!("{source} has no such file {from}."),
+        );
+    }
+    if names.contains_key(to) {
+        return make_error(
+            ClientResponse::RenameFile,
+            &format!("{source} already has a file {to}."),
+        );
+    }
+
+    // contents are stored by hash, not name, so only the index changes
+    let file = names.remove(from).unwrap();
+    names.insert(to.to_string(), file);
+    save_state(state);
+    Response::new()
+        .body(ClientResponse::RenameFile(Ok(())))
+        .send()?;
+    Ok(())
+}
+
+fn handle_usage(source: &str, state: &State) -> anyhow::Result<()> {
+    Response::new()
+        .body(ClientResponse::Usage(Ok(make_usage(source, state))))
+        .send()?;
+    Ok(())
+}
+
+fn handle_set_quotas(
+    quotas: Quotas,
+    our: &Address,
+    source: &str,
+    state: &mut State,
+) -> anyhow::Result<()> {
+    if source != our.node() {
+        return make_error(
+            ClientResponse::SetQuotas,
+            &format!("Only {} may SetQuotas.", our.node()),
+        );
+    }
+    state.quotas = quotas;
+    save_state(state);
+    Response::new()
+        .body(ClientResponse::SetQuotas(Ok(())))
+        .send()?;
+    Ok(())
+}
+
+fn handle_restore(
+    request: RestoreRequest,
+    our: &PackageId,
+    source: &str,
+    state: &mut State,
+) -> anyhow::Result<()> {
+    let RestoreRequest { name, version } = request;
+    let Some(file) = state
+        .files
+        .get_mut(source)
+        .and_then(|files| files.get_mut(&name))
+    else {
+        return make_error(
+            ClientResponse::Restore,
+            &format!("{source} has no such file {name}."),
+        );
+    };
+    if file.current.version == version {
+        return make_error(
+            ClientResponse::Restore,
+            &format!("Version {version} of {name} is already current."),
+        );
+    }
+    let Some(index) = file.earlier.iter().position(|v| v.version == version) else {
+        return make_error(
+            ClientResponse::Restore,
+            &format!("{name} has no such version {version}."),
+        );
+    };
+
+    // take the restored version out first, so it is not dropped
+    // to make room for the version it replaces
+    let restored = file.earlier.remove(index);
+    let unreferenced = keep_current_version(&name, source, state);
+    let file = state.files.get_mut(source).unwrap().get_mut(&name).unwrap();
+    file.current = restored;
+    save_state(state);
+    remove_blobs(our, unreferenced);
+    Response::new()
+        .body(ClientResponse::Restore(Ok(())))
+        .send()?;
+    Ok(())
+}
+
+fn handle_begin_upload(
+    request: BeginUploadRequest,
+    our: &PackageId,
+    source: &str,
+    state: &mut State,
+) -> anyhow::Result<()> {
+    expire_uploads(our, state);
+    let uploads = state
+        .uploads
+        .values()
+        .filter(|upload| upload.source == source)
+        .count();
+    if uploads >= MAX_CLIENT_UPLOADS {
+        return make_error(
+            ClientResponse::BeginUpload,
+            &format!("{source} already has {MAX_CLIENT_UPLOADS} uploads in progress."),
+        );
+    }
+    if let Err(e) = check_quotas(&request.name, request.size, source, state) {
+        return make_error(ClientResponse::BeginUpload, &e);
+    }
+
+    let upload = state.next_upload;
+    if let Err(e) = vfs::create_drive(our.clone(), UPLOADS_DRIVE, None)
+        .and_then(|_| vfs::create_file(&make_upload_path(our, upload), None))
+    {
+        return make_error(
+            ClientResponse::BeginUpload,
+            &format!("Could not begin upload: {e}"),
+        );
+    }
+    state.next_upload += 1;
+    state.uploads.insert(
+        upload,
+        Upload {
+            source: source.to_string(),
+            name: request.name,
+            size: request.size,
+            received: 0,
+            updated: now(),
+        },
+    );
+    save_state(state);
+    Response::new()
+        .body(ClientResponse::BeginUpload(Ok(upload)))
+        .send()?;
+    Ok(())
+}
+
+fn handle_upload_chunk(
+    request: UploadChunkRequest,
+    our: &PackageId,
+    source: &str,
+    state: &mut State,
+) -> anyhow::Result<()> {
+    let id = request.upload;
+    let Some(upload) = state
+        .uploads
+        .get_mut(&id)
+        .filter(|upload| upload.source == source)
+    else {
+        return make_error(
+            ClientResponse::UploadChunk,
+            &format!("{source} has no such upload {id}."),
+        );
+    };
+    if request.offset != upload.received {
+        return make_error(
+            ClientResponse::UploadChunk,
+            &format!(
+                "Expected chunk at offset {}, not {}.",
+                upload.received, request.offset,
+            ),
+        );
+    }
+    let Some(ref blob) = get_blob() else {
+        return make_error(
+            ClientResponse::UploadChunk,
+            "Must give a chunk in the blob.",
+        );
+    };
+    let length = blob.bytes().len() as u64;
+    if length > MAX_CHUNK_BYTES {
+        return make_error(
+            ClientResponse::UploadChunk,
+            &format!("Chunk exceeds {MAX_CHUNK_BYTES} bytes."),
+        );
+    }
+    if upload.received + length > upload.size {
+        return make_error(
+            ClientResponse::UploadChunk,
+            "Chunk exceeds the size given in BeginUpload.",
+        );
+    }
+
+    if let Err(e) = vfs::open_file(&make_upload_path(our, id), false, None)
+        .and_then(|mut file| file.append(blob.bytes()))
+    {
+        return make_error(
+            ClientResponse::UploadChunk,
+            &format!("Could not store chunk: {e}"),
+        );
+    }
+    upload.received += length;
+    upload.updated = now();
+    save_state(state);
+    Response::new()
+        .body(ClientResponse::UploadChunk(Ok(())))
+        .send()?;
+    Ok(())
+}
+
+fn handle_commit_upload(
+    id: u64,
+    our: &PackageId,
+    source: &str,
+    state: &mut State,
+) -> anyhow::Result<()> {
+    let Some(upload) = state
+        .uploads
+        .get(&id)
+        .filter(|upload| upload.source == source)
+    else {
+        return make_error(
+            ClientResponse::CommitUpload,
+            &format!("{source} has no such upload {id}."),
+        );
+    };
+    if upload.received != upload.size {
+        return make_error(
+            ClientResponse::CommitUpload,
+            &format!(
+                "Upload {id} has {} of {} bytes.",
+                upload.received, upload.size,
+            ),
+        );
+    }
+    // quotas may have changed since the upload began: check against
+    // them, but not against what this upload itself reserved
+    let upload = state.uploads.remove(&id).unwrap();
+    if let Err(e) = check_quotas(&upload.name, upload.size, source, state) {
+        state.uploads.insert(id, upload);
+        return make_error(ClientResponse::CommitUpload, &e);
+    }
+
+    let hash = match store_upload(id, upload.size, our, state) {
+        Ok(hash) => hash,
+        Err(e) => {
+            state.uploads.insert(id, upload);
+            return make_error(
+                ClientResponse::CommitUpload,
+                &format!("Could not commit upload {id}: {e}"),
+            );
+        }
     };
-    let mut names: Vec<String> = names.iter().cloned().collect();
-    names.sort();
+    reference_blob(&hash, state);
+    let unreferenced = keep_current_version(&upload.name, source, state);
+    record_current_version(&upload.name, upload.size, hash, source, state);
+    save_state(state);
+    remove_blobs(our, unreferenced);
     Response::new()
-        .body(ClientResponse::ListFiles(Ok(names)))
+        .body(ClientResponse::CommitUpload(Ok(())))
+        .send()?;
+    Ok(())
+}
+
+fn handle_abort_upload(
+    id: u64,
+    our: &PackageId,
+    source: &str,
+    state: &mut State,
+) -> anyhow::Result<()> {
+    if !state
+        .uploads
+        .get(&id)
+        .is_some_and(|upload| upload.source == source)
+    {
+        return make_error(
+            ClientResponse::AbortUpload,
+            &format!("{source} has no such upload {id}."),
+        );
+    }
+
+    // any upload left behind is removed by `collect_garbage()`
+    let _ = vfs::remove_file(&make_upload_path(our, id), None);
+    state.uploads.remove(&id);
+    save_state(state);
+    Response::new()
+        .body(ClientResponse::AbortUpload(Ok(())))
+        .send()?;
+    Ok(())
+}
+
+fn handle_read_range(
+    request: ReadRangeRequest,
+    our: &PackageId,
+    source: &str,
+    state: &State,
+) -> anyhow::Result<()> {
+    let name = request.name;
+    let Some((path, found)) = find_version(&name, request.version, our, source, state) else {
+        return make_error(
+            ClientResponse::ReadRange,
+            &format!("{source} has no such version of {name}."),
+        );
+    };
+    let size = found.size;
+    if request.offset > size {
+        return make_error(
+            ClientResponse::ReadRange,
+            &format!("Offset {} is past the end of {name}.", request.offset,),
+        );
+    }
+
+    let length = request
+        .length
+        .min(MAX_CHUNK_BYTES)
+        .min(size - request.offset);
+    let range = match read_vfs_range(&path, request.offset, length) {
+        Ok(range) => range,
+        Err(e) => {
+            return make_error(
+                ClientResponse::ReadRange,
+                &format!("Could not read {name}: {e}"),
+            )
+        }
+    };
+    Response::new()
+        .body(ClientResponse::ReadRange(Ok(RangeInfo {
+            version: found.version,
+            size,
+        })))
+        .blob_bytes(range)
         .send()?;
     Ok(())
 }
@@ -117,10 +968,36 @@ fn handle_message(our: &Address, message: &Message, state: &mut State) -> anyhow
         ClientRequest::PutFile(ref name) => {
             handle_put_file(name, &our.package_id(), source.node(), state)?
         }
-        ClientRequest::GetFile(ref name) => {
-            handle_get_file(name, &our.package_id(), source.node(), state)?
+        ClientRequest::GetFile(request) => {
+            handle_get_file(request, &our.package_id(), source.node(), state)?
+        }
+        ClientRequest::ListFiles(versions) => handle_list_files(versions, source.node(), state)?,
+        ClientRequest::DeleteFile(ref name) => {
+            handle_delete_file(name, &our.package_id(), source.node(), state)?
+        }
+        ClientRequest::RenameFile((ref from, ref to)) => {
+            handle_rename_file(from, to, source.node(), state)?
+        }
+        ClientRequest::Usage => handle_usage(source.node(), state)?,
+        ClientRequest::SetQuotas(quotas) => handle_set_quotas(quotas, our, source.node(), state)?,
+        ClientRequest::Restore(request) => {
+            handle_restore(request, &our.package_id(), source.node(), state)?
+        }
+        ClientRequest::BeginUpload(request) => {
+            handle_begin_upload(request, &our.package_id(), source.node(), state)?
+        }
+        ClientRequest::UploadChunk(request) => {
+            handle_upload_chunk(request, &our.package_id(), source.node(), state)?
+        }
+        ClientRequest::CommitUpload(upload) => {
+            handle_commit_upload(upload, &our.package_id(), source.node(), state)?
+        }
+        ClientRequest::AbortUpload(upload) => {
+            handle_abort_upload(upload, &our.package_id(), source.node(), state)?
+        }
+        ClientRequest::ReadRange(request) => {
+            handle_read_range(request, &our.package_id(), source.node(), state)?
         }
-        ClientRequest::ListFiles => handle_list_files(source.node(), state)?,
     }
     Ok(())
 }
@@ -129,7 +1006,7 @@ call_init!(init);
 fn init(our: Address) {
     println!("begin");
 
-    let mut state: State = HashMap::new();
+    let mut state = load_state(&our.package_id());
 
     loop {
         match await_message() {
@@ -0,0 +1,49 @@
+use serde::Serialize;
+use serde_json::Value;
+
+/// Version of the layout `State` is saved in
+pub const STATE_VERSION: u32 = 0;
+
+type Migration = fn(Value) -> Result<Value, String>;
+
+/// `MIGRATIONS[i]` upgrades a state saved in version `i` to version `i + 1`
+const MIGRATIONS: [Migration; STATE_VERSION as usize] = [];
+
+/// A state tagged with the version of its layout
+#[derive(Serialize)]
+pub struct Versioned<'a, T> {
+    version: u32,
+    state: &'a T,
+}
+
+impl<'a, T> Versioned<'a, T> {
+    pub fn new(state: &'a T) -> Self {
+        Versioned {
+            version: STATE_VERSION,
+            state,
+        }
+    }
+}
+
+/// Upgrade saved `bytes` to the current layout, one version at a time.
+pub fn upgrade(bytes: &[u8]) -> Result<Value, String> {
+    let saved: Value =
+        serde_json::from_slice(bytes).map_err(|e| format!("Malformed state: {e}"))?;
+    let Value::Object(mut saved) = saved else {
+        return Err("Malformed state".to_string());
+    };
+    let version = saved
+        .get("version")
+        .and_then(Value::as_u64)
+        .ok_or("Malformed state version")? as usize;
+    let mut state = saved.remove("state").ok_or("Missing state")?;
+    if version > MIGRATIONS.len() {
+        return Err(format!(
+            "State version {version} is newer than {STATE_VERSION}"
+        ));
+    }
+    for migration in &MIGRATIONS[version..] {
+        state = migration(state)?;
+    }
+    Ok(state)
+}
@@ -1,9 +1,14 @@
-use crate::kinode::process::server::{get_file, list_files, put_file};
+use crate::kinode::process::server::{
+    delete_file, download_file, get_file, list_files, put_file, rename_file, restore, usage,
+    BeginUploadRequest, ClientRequest, ClientResponse,
+};
 use crate::kinode::process::tester::{
     FailResponse, Request as TesterRequest, Response as TesterResponse, RunRequest,
 };
 use kinode_process_lib::{
-    await_message, call_init, get_blob, println, vfs::File, Address, Response,
+    await_message, call_init, get_blob, println,
+    vfs::{self, File},
+    Address, Request, Response,
 };
 
 mod tester_lib;
@@ -15,6 +20,9 @@ wit_bindgen::generate!({
     additional_derives: [serde::Deserialize, serde::Serialize, process_macros::SerdeJsonInto],
 });
 
+/// Larger than one chunk, and not a multiple of it
+const LARGE_FILE_BYTES: usize = 3 * 1024 * 1024 / 2 + 1;
+
 fn run_test(our: &Address) -> anyhow::Result<()> {
     let message = await_message()?;
 
@@ -45,15 +53,23 @@ fn run_test(our: &Address) -> anyhow::Result<()> {
     }
 
     // check file is on worker
-    let Ok(files) = list_files(worker) else {
+    let Ok(files) = list_files(worker, false) else {
         fail!("file-storage-test");
     };
-    if files != vec!["manifest.json"] {
+    if files.len() != 1 || files[0].name != "manifest.json" {
+        fail!("file-storage-test");
+    }
+
+    // check the file counts against our quota on worker
+    let Ok(usage) = usage(worker) else {
+        fail!("file-storage-test");
+    };
+    if usage.files != 1 || usage.bytes == 0 {
         fail!("file-storage-test");
     }
 
     // read out the file on worker from master
-    if get_file(worker, "manifest.json").is_err() {
+    if get_file(worker, "manifest.json", None).is_err() {
         fail!("file-storage-test");
     }
     let Some(contents) = get_blob() else {
@@ -71,6 +87,119 @@ fn run_test(our: &Address) -> anyhow::Result<()> {
         fail!("file-storage-test");
     }
 
+    // put the file again: the first copy is kept as an earlier version
+    if put_file(worker, file_path, "manifest.json").is_err() {
+        fail!("file-storage-test");
+    }
+    let Ok(files) = list_files(worker, true) else {
+        fail!("file-storage-test");
+    };
+    if files.len() != 1 || files[0].version != 1 || files[0].earlier.len() != 1 {
+        fail!("file-storage-test");
+    }
+
+    // read out the earlier version, then make it current again
+    if get_file(worker, "manifest.json", Some(0)).is_err() {
+        fail!("file-storage-test");
+    }
+    let Some(contents) = get_blob() else {
+        fail!("file-storage-test");
+    };
+    if contents.bytes() != expected_contents {
+        fail!("file-storage-test");
+    }
+    if restore(worker, "manifest.json", 0).is_err() {
+        fail!("file-storage-test");
+    }
+    let Ok(files) = list_files(worker, true) else {
+        fail!("file-storage-test");
+    };
+    if files.len() != 1
+        || files[0].version != 0
+        || files[0].earlier.len() != 1
+        || files[0].earlier[0].version != 1
+    {
+        fail!("file-storage-test");
+    }
+
+    // put the same contents under a second name: they are stored once,
+    // so deleting one name must leave the other readable
+    if put_file(worker, file_path, "copy.json").is_err() {
+        fail!("file-storage-test");
+    }
+    if delete_file(worker, "copy.json").is_err() {
+        fail!("file-storage-test");
+    }
+    if get_file(worker, "manifest.json", None).is_err() {
+        fail!("file-storage-test");
+    }
+    let Some(contents) = get_blob() else {
+        fail!("file-storage-test");
+    };
+    if contents.bytes() != expected_contents {
+        fail!("file-storage-test");
+    }
+
+    // rename the file on worker
+    if rename_file(worker, "manifest.json", "renamed.json").is_err() {
+        fail!("file-storage-test");
+    }
+    let Ok(files) = list_files(worker, false) else {
+        fail!("file-storage-test");
+    };
+    if files.len() != 1 || files[0].name != "renamed.json" {
+        fail!("file-storage-test");
+    }
+
+    // delete the file from worker
+    if delete_file(worker, "renamed.json").is_err() {
+        fail!("file-storage-test");
+    }
+    let Ok(files) = list_files(worker, false) else {
+        fail!("file-storage-test");
+    };
+    if !files.is_empty() {
+        fail!("file-storage-test");
+    }
+
+    // put a file too large for one message: it is uploaded in chunks
+    let large_contents: Vec<u8> = (0..LARGE_FILE_BYTES).map(|i| (i % 251) as u8).collect();
+    let drive = vfs::create_drive(our.package_id(), "files", None)?;
+    let large_path = format!("{drive}/large.bin");
+    vfs::create_file(&large_path, None)?.write(&large_contents)?;
+    if put_file(worker, &large_path, "large.bin").is_err() {
+        fail!("file-storage-test");
+    }
+
+    // it is too large to get in one blob, so download it in ranges
+    if get_file(worker, "large.bin", None).is_ok() {
+        fail!("file-storage-test");
+    }
+    let copy_path = format!("{drive}/copy.bin");
+    if download_file(worker, "large.bin", None, &copy_path).is_err() {
+        fail!("file-storage-test");
+    }
+    if vfs::open_file(&copy_path, false, None)?.read()? != large_contents {
+        fail!("file-storage-test");
+    }
+
+    // uploads beyond the quotas, or too large to count, are rejected
+    for size in [1 << 40, u64::MAX] {
+        let response = Request::new()
+            .target((worker.as_str(), "server", "server", "template.os"))
+            .body(ClientRequest::BeginUpload(BeginUploadRequest {
+                name: "huge.bin".to_string(),
+                size,
+            }))
+            .send_and_await_response(5)??;
+        let Ok(ClientResponse::BeginUpload(result)) = response.body().try_into() else {
+            fail!("file-storage-test");
+        };
+        if result.is_ok() {
+            fail!("file-storage-test");
+        }
+    }
+
     Response::new().body(TesterResponse::Run(Ok(()))).send()?;
     Ok(())
 }